@@ -0,0 +1,38 @@
+use std::time::{ Duration, Instant };
+
+use moonlib::common::utils::compile_file;
+use moonlib::vm::VM;
+
+const RUNS: u32 = 5;
+
+fn bench(file: &str) -> Result<(), String> {
+  let closure = compile_file(file.into())?;
+  let mut times = Vec::with_capacity(RUNS as usize);
+
+  for _ in 0 .. RUNS {
+    let mut vm = VM::new(closure.clone());
+
+    let start = Instant::now();
+    vm.run()?;
+    times.push(start.elapsed());
+  }
+
+  let min = times.iter().min().unwrap();
+  let avg = times.iter().sum::<Duration>() / RUNS;
+
+  println!("{:<24} min {:>10.3?}  avg {:>10.3?}", file, min, avg);
+  Ok(())
+}
+
+fn main() {
+  let files = [
+    "examples/bench.mn",
+    "examples/fib.mn"
+  ];
+
+  for file in files.iter() {
+    if let Err(e) = bench(file) {
+      eprintln!("{}", e);
+    }
+  }
+}
@@ -1,12 +1,14 @@
 use std::fmt::{ Debug, Formatter, Result as FmtResult };
 use std::hash::{ Hash, Hasher };
+use std::cmp::Ordering;
+use std::convert::TryFrom;
 use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::vm::error::RuntimeError;
-use crate::common::{ Value, Type };
+use crate::common::{ Value, Type, value::drop_values };
 
-#[derive(Clone, PartialEq, PartialOrd)]
+#[derive(Clone)]
 pub struct Array {
   pub vec: Rc<RefCell<Vec<Value>>>
 }
@@ -33,6 +35,10 @@ impl Array {
   }
 
   pub fn validate_index(idx: &Value) -> Result<usize, RuntimeError> {
+    if let Value::Integer(idx) = idx {
+      return usize::try_from(*idx).map_err(| _ | RuntimeError::ArrayIdxNeg)
+    }
+
     if let Value::Number(idx) = idx {
       let idx = *idx;
 
@@ -82,12 +88,33 @@ impl Array {
   }
 }
 
+impl Drop for Array {
+  fn drop(&mut self) {
+    if Rc::strong_count(&self.vec) == 1 && !self.vec.borrow().is_empty() {
+      drop_values(std::mem::take(&mut *self.vec.borrow_mut()));
+    }
+  }
+}
+
 impl Debug for Array {
   fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
     write!(fmt, "{:?}", self.vec)
   }
 }
 
+/// arrays are compared by reference, `table.deep_equal` compares contents
+impl PartialEq for Array {
+  fn eq(&self, other: &Array) -> bool {
+    Rc::ptr_eq(&self.vec, &other.vec)
+  }
+}
+
+impl PartialOrd for Array {
+  fn partial_cmp(&self, _other: &Array) -> Option<Ordering> {
+    None
+  }
+}
+
 impl Hash for Array {
   fn hash<H>(&self, state: &mut H) where H: Hasher {
     Rc::as_ptr(&self.vec).hash(state)
@@ -7,7 +7,10 @@ pub struct Closure {
   pub name: String,
   pub file_name: String,
   pub lines: Vec<usize>,
-  pub upvals: Rc<RefCell<Vec<(u8, Value)>>>,
+  /// `(frame, register, value)`, the id of the frame that made the closure
+  /// and the register the value is written back to when it returns, every
+  /// closure made by `Opcode::Closure` has its own
+  pub upvals: Rc<RefCell<Vec<(usize, usize, Value)>>>,
   pub code: Vec<u32>,
   pub consts: Vec<Value>,
   pub nparams: u8
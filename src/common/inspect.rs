@@ -0,0 +1,180 @@
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use crate::common::Value;
+
+/// Options for `inspect`, the default shows everything on one line.
+#[derive(Default, Clone, Copy)]
+pub struct InspectOptions {
+  /// how many levels of tables and arrays are shown, `None` for up to
+  /// `MAX_DEPTH` of them
+  pub depth: Option<usize>,
+  /// spaces per level, `None` puts everything on one line
+  pub indent: Option<usize>
+}
+
+/// deeper tables and arrays are never shown, the recursion would overflow
+/// the stack
+const MAX_DEPTH: usize = 200;
+
+const KEYWORDS: [&str; 10] = ["let", "if", "else", "fn", "return", "while", "true", "false", "nil", "for"];
+
+/// Shows a value as a Moon literal: strings are quoted, table keys are
+/// sorted and a table or array that contains itself shows `<cycle>` instead
+/// of being shown again. Functions have no literal so they're shown as
+/// `<function: name>`, a table with a `__name` metafield is shown as that
+/// name, e.g. `json.null`.
+pub fn inspect(val: &Value, opts: InspectOptions) -> String {
+  let mut out = String::new();
+  let mut path = Vec::new();
+
+  write_value(&mut out, val, opts, &mut path);
+  out
+}
+
+/// a string literal that reads back as the same string
+fn quote_str(s: &str) -> String {
+  let mut str = String::with_capacity(s.len() + 2);
+  str.push('"');
+
+  for c in s.chars() {
+    match c {
+      '"' => str.push_str("\\\""),
+      '\\' => str.push_str("\\\\"),
+      '\n' => str.push_str("\\n"),
+      '\r' => str.push_str("\\r"),
+      '\t' => str.push_str("\\t"),
+      '\0' => str.push_str("\\0"),
+      c if c.is_control() => str.push_str(&format!("\\u{{{:x}}}", c as u32)),
+
+      c => str.push(c)
+    }
+  }
+
+  str.push('"');
+  str
+}
+
+/// `path` has the tables and arrays being shown, to find cycles
+fn write_value(out: &mut String, val: &Value, opts: InspectOptions, path: &mut Vec<usize>) {
+  match val {
+    Value::String(s) => out.push_str(&quote_str(s)),
+    Value::Number(n) if n.is_nan() => out.push_str("(0/0)"),
+    Value::Number(n) if n.is_infinite() => out.push_str(if *n > 0.0 { "1e9999" } else { "-1e9999" }),
+    Value::Number(n) => out.push_str(&format!("{:?}", n)),
+    Value::Closure(c) => out.push_str(&format!("<function: {}>", c.name)),
+    Value::NativeFunc(nf) => out.push_str(&format!("<function: {}>", nf.name)),
+
+    Value::Array(a) => {
+      let ptr = Rc::as_ptr(&a.vec) as usize;
+      let vals = a.vec.borrow().clone();
+
+      write_container(out, ptr, ('[', ']'), vals.len(), opts, path, | out, i, path | {
+        write_value(out, &vals[i], opts, path)
+      })
+    }
+
+    Value::Table(t) => {
+      if let Value::String(name) = t.get_meta("__name") {
+        out.push_str(&name);
+        return
+      }
+
+      let ptr = Rc::as_ptr(&t.tbl) as usize;
+
+      let mut entries = t.tbl.borrow().iter()
+        .map(| (k, v) | (k.clone(), v.clone()))
+        .collect::<Vec<_>>();
+
+      entries.sort_by(| (a, _), (b, _) | key_order(a, b));
+
+      write_container(out, ptr, ('{', '}'), entries.len(), opts, path, | out, i, path | {
+        let (k, v) = &entries[i];
+
+        match k {
+          Value::String(s) if is_name(s) => out.push_str(s),
+
+          _ => {
+            out.push('[');
+            write_value(out, k, opts, path);
+            out.push(']');
+          }
+        }
+
+        out.push_str(": ");
+        write_value(out, v, opts, path)
+      })
+    }
+
+    _ => out.push_str(&val.to_string())
+  }
+}
+
+fn write_container<F>(out: &mut String, ptr: usize, (open, close): (char, char), len: usize, opts: InspectOptions, path: &mut Vec<usize>, mut write_item: F)
+  where F: FnMut(&mut String, usize, &mut Vec<usize>) {
+  if path.contains(&ptr) {
+    out.push_str("<cycle>");
+    return
+  }
+
+  out.push(open);
+
+  if len == 0 {
+    out.push(close);
+    return
+  }
+
+  if path.len() >= opts.depth.unwrap_or(MAX_DEPTH).min(MAX_DEPTH) {
+    out.push_str("...");
+    out.push(close);
+    return
+  }
+
+  path.push(ptr);
+
+  for i in 0 .. len {
+    if i > 0 { out.push(',') }
+
+    match opts.indent {
+      Some(indent) => {
+        out.push('\n');
+        out.push_str(&" ".repeat(indent * path.len()));
+      }
+
+      None if i > 0 => out.push(' '),
+      None => {}
+    }
+
+    write_item(out, i, path);
+  }
+
+  path.pop();
+
+  if let Some(indent) = opts.indent {
+    out.push('\n');
+    out.push_str(&" ".repeat(indent * path.len()));
+  }
+
+  out.push(close);
+}
+
+/// keys that can be written without brackets
+fn is_name(s: &str) -> bool {
+  let mut chars = s.chars();
+
+  let start = match chars.next() {
+    Some(c) => c.is_ascii_alphabetic() || c == '_',
+    None => false
+  };
+
+  start && chars.all(| c | c.is_ascii_alphanumeric() || c == '_') && !KEYWORDS.contains(&s)
+}
+
+/// strings, then numbers, then everything else, values that can't be ordered
+/// are sorted by how they're shown
+fn key_order(a: &Value, b: &Value) -> Ordering {
+  match a.partial_cmp(b) {
+    Some(ord) if ord != Ordering::Equal || a == b => ord,
+    _ => inspect(a, InspectOptions::default()).cmp(&inspect(b, InspectOptions::default()))
+  }
+}
@@ -1,12 +1,16 @@
 pub mod utils;
 mod closure;
+mod string;
 mod opcode;
 mod value;
 mod array;
 mod table;
+mod inspect;
 
 pub use closure::Closure;
+pub use string::Str;
 pub use array::Array;
 pub use table::Table;
 pub use value::{ Value, Type, BuiltIn, RustFunc };
+pub use inspect::{ inspect, InspectOptions };
 pub use opcode::{ Opcode, Opmode, OPMODES };
\ No newline at end of file
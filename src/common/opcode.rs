@@ -32,6 +32,9 @@ pub enum Opcode {
   /// A B | `Reg[A] = [ Reg[A+1] .. Reg[B] ]`
   NewArray,
 
+  /// A B | `Reg[A] = Reg[A] .. ... .. Reg[B-1]`
+  Concat,
+
   /// A B | `Reg[A] = Reg[A][RC(B)]
   GetObj,
 
@@ -46,8 +49,22 @@ pub enum Opcode {
   Mul,
   /// A B C | `Reg[C] = RC[A] / RC[B]`
   Div,
+  /// A B C | `Reg[C] = RC[A] // RC[B]`
+  IDiv,
   /// A B C | `Reg[C] = RC[A] % RC[B]`
   Mod,
+  /// A B C | `Reg[C] = RC[A] ** RC[B]`
+  Pow,
+  /// A B C | `Reg[C] = RC[A] & RC[B]`
+  BAnd,
+  /// A B C | `Reg[C] = RC[A] | RC[B]`
+  BOr,
+  /// A B C | `Reg[C] = RC[A] ^ RC[B]`
+  BXor,
+  /// A B C | `Reg[C] = RC[A] << RC[B]`
+  Shl,
+  /// A B C | `Reg[C] = RC[A] >> RC[B]`
+  Shr,
 
 
   /// A B C | `Reg[C] = RC[A] == RC[B]`
@@ -67,6 +84,8 @@ pub enum Opcode {
   Neg,
   /// A B | `Reg[A] = !RC[B]`
   Not,
+  /// A B | `Reg[A] = ~RC[B]`
+  BNot,
 
   /// A Bx | `if A then pc -= Bx else pc += Bx`
   Jmp,
@@ -77,6 +96,9 @@ pub enum Opcode {
   /// A B C | `Reg[C] = Reg[A](Reg(A+1) .. Reg(B))`
   Call,
 
+  /// A B C | `return Reg[A](Reg(A+1) .. Reg(B))`, reusing the current frame
+  TailCall,
+
   /// A Bx | `Reg[A] = Consts[Bx]`
   Closure,
 
@@ -104,13 +126,21 @@ pub static OPMODES: &[(&'static str, Opmode)] = &[
   ("SetGlobal ", Opmode::Abx),
   ("NewTable  ", Opmode::Abc),
   ("NewArray  ", Opmode::Abc),
+  ("Concat    ", Opmode::Abc),
   ("GetObj    ", Opmode::Abc),
   ("SetObj    ", Opmode::Abc),
   ("Add       ", Opmode::Abc),
   ("Sub       ", Opmode::Abc),
   ("Mul       ", Opmode::Abc),
   ("Div       ", Opmode::Abc),
+  ("IDiv      ", Opmode::Abc),
   ("Mod       ", Opmode::Abc),
+  ("Pow       ", Opmode::Abc),
+  ("BAnd      ", Opmode::Abc),
+  ("BOr       ", Opmode::Abc),
+  ("BXor      ", Opmode::Abc),
+  ("Shl       ", Opmode::Abc),
+  ("Shr       ", Opmode::Abc),
   ("Eq        ", Opmode::Abc),
   ("Neq       ", Opmode::Abc),
   ("Gt        ", Opmode::Abc),
@@ -119,9 +149,11 @@ pub static OPMODES: &[(&'static str, Opmode)] = &[
   ("Le        ", Opmode::Abc),
   ("Neg       ", Opmode::Abc),
   ("Not       ", Opmode::Abc),
+  ("BNot      ", Opmode::Abc),
   ("Jmp       ", Opmode::Abx),
   ("Test      ", Opmode::Abc),
   ("Call      ", Opmode::Abc),
+  ("TailCall  ", Opmode::Abc),
   ("Closure   ", Opmode::Abx),
   ("Return    ", Opmode::Abc),
   ("Close     ", Opmode::Abc)
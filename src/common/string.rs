@@ -0,0 +1,204 @@
+use std::fmt::{ Debug, Display, Formatter, Result as FmtResult };
+use std::hash::{ Hash, Hasher };
+use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::cell::RefCell;
+use std::rc::{ Rc, Weak };
+use std::ops::Deref;
+
+/// strings up to this length are interned, so comparing them is comparing pointers
+const MAX_SHORT_LEN: usize = 40;
+
+/// An immutable, reference-counted string with a cached hash. Cloning one is
+/// cheap and short strings are interned, so equal short strings share the
+/// same allocation.
+///
+/// Scripts index strings by code point, so the number of code points is
+/// cached too and ASCII strings can be indexed by byte.
+#[derive(Clone)]
+pub struct Str {
+  s: Rc<str>,
+  hash: u64,
+  chars: usize
+}
+
+struct Interner {
+  strings: HashMap<u64, Vec<Weak<str>>>,
+  len: usize,
+  threshold: usize
+}
+
+thread_local! {
+  static INTERNER: RefCell<Interner> = RefCell::new(Interner {
+    strings: HashMap::new(),
+    len: 0,
+    threshold: 1024
+  });
+}
+
+// FNV-1a
+fn hash_str(s: &str) -> u64 {
+  let mut hash: u64 = 0xcbf29ce484222325;
+
+  for b in s.bytes() {
+    hash ^= b as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+
+  hash
+}
+
+impl Interner {
+  fn intern(&mut self, s: &str, hash: u64) -> Rc<str> {
+    let bucket = self.strings.entry(hash).or_default();
+
+    for weak in bucket.iter() {
+      if let Some(rc) = weak.upgrade() {
+        if &*rc == s { return rc }
+      }
+    }
+
+    let rc: Rc<str> = Rc::from(s);
+    bucket.push(Rc::downgrade(&rc));
+    self.len += 1;
+
+    if self.len >= self.threshold {
+      self.prune();
+    }
+
+    rc
+  }
+
+  /// forgets the strings that were freed
+  fn prune(&mut self) {
+    self.strings.retain(| _, bucket | {
+      bucket.retain(| weak | weak.strong_count() > 0);
+      !bucket.is_empty()
+    });
+
+    self.len = self.strings.values().map(| b | b.len()).sum();
+    self.threshold = (self.len * 2).max(1024);
+  }
+}
+
+impl Str {
+  pub fn new(s: &str) -> Self {
+    let hash = hash_str(s);
+
+    let s = if s.len() <= MAX_SHORT_LEN {
+      INTERNER.with(| i | i.borrow_mut().intern(s, hash))
+    } else {
+      Rc::from(s)
+    };
+
+    let chars = if s.is_ascii() { s.len() } else { s.chars().count() };
+
+    Str { s, hash, chars }
+  }
+
+  #[inline]
+  pub fn as_str(&self) -> &str {
+    &self.s
+  }
+
+  /// number of code points
+  #[inline]
+  pub fn char_len(&self) -> usize {
+    self.chars
+  }
+
+  #[inline]
+  pub fn is_ascii(&self) -> bool {
+    self.chars == self.s.len()
+  }
+
+  /// the code point at `idx`
+  pub fn char_at(&self, idx: usize) -> Option<char> {
+    if self.is_ascii() {
+      self.s.as_bytes().get(idx).map(| b | *b as char)
+    } else {
+      self.s.chars().nth(idx)
+    }
+  }
+
+  /// byte offset of the code point at `idx`, or the length if it's past the end
+  pub fn char_offset(&self, idx: usize) -> usize {
+    if self.is_ascii() {
+      idx.min(self.s.len())
+    } else {
+      self.s.char_indices().nth(idx).map(| (i, _) | i).unwrap_or(self.s.len())
+    }
+  }
+
+  #[inline]
+  fn is_short(&self) -> bool {
+    self.s.len() <= MAX_SHORT_LEN
+  }
+}
+
+impl Deref for Str {
+  type Target = str;
+
+  #[inline]
+  fn deref(&self) -> &str {
+    &self.s
+  }
+}
+
+impl From<&str> for Str {
+  fn from(s: &str) -> Self {
+    Str::new(s)
+  }
+}
+
+impl From<String> for Str {
+  fn from(s: String) -> Self {
+    Str::new(&s)
+  }
+}
+
+impl PartialEq for Str {
+  #[inline]
+  fn eq(&self, rhs: &Str) -> bool {
+    if Rc::ptr_eq(&self.s, &rhs.s) {
+      return true
+    }
+
+    // different short strings are always different allocations
+    !self.is_short() && self.hash == rhs.hash && self.s == rhs.s
+  }
+}
+
+impl Eq for Str {}
+
+impl Hash for Str {
+  #[inline]
+  fn hash<H>(&self, state: &mut H) where H: Hasher {
+    state.write_u64(self.hash)
+  }
+}
+
+impl PartialOrd for Str {
+  fn partial_cmp(&self, rhs: &Str) -> Option<Ordering> {
+    Some(self.cmp(rhs))
+  }
+}
+
+/// UTF-8 keeps the order of code points so comparing bytes is enough
+impl Ord for Str {
+  fn cmp(&self, rhs: &Str) -> Ordering {
+    self.s.cmp(&rhs.s)
+  }
+}
+
+impl Display for Str {
+  fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+    write!(fmt, "{}", &*self.s)
+  }
+}
+
+impl Debug for Str {
+  fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+    write!(fmt, "{:?}", &*self.s)
+  }
+}
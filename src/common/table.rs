@@ -6,17 +6,47 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::vm::error::RuntimeError;
-use crate::common::Value;
+use crate::common::{ Value, value::drop_values };
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone)]
 pub struct Table {
-  pub tbl: Rc<RefCell<HashMap<Value, Value>>>
+  pub tbl: Rc<RefCell<HashMap<Value, Value>>>,
+  pub meta: Rc<RefCell<Option<Table>>>
 }
 
 impl Table {
   pub fn new() -> Self {
     Table {
-      tbl: Rc::new(RefCell::new(HashMap::new()))
+      tbl: Rc::new(RefCell::new(HashMap::new())),
+      meta: Rc::new(RefCell::new(None))
+    }
+  }
+
+  #[inline]
+  pub fn metatable(&self) -> Option<Table> {
+    self.meta.borrow().clone()
+  }
+
+  #[inline]
+  pub fn set_metatable(&self, meta: Option<Table>) {
+    *self.meta.borrow_mut() = meta;
+  }
+
+  /// returns the field `name` of the metatable or nil
+  pub fn get_meta(&self, name: &str) -> Value {
+    if let Some(meta) = &*self.meta.borrow() {
+      meta.tbl.borrow().get(&Value::String(name.into())).unwrap_or(&Value::Nil).clone()
+    } else {
+      Value::Nil
+    }
+  }
+
+  /// returns if the keys and the values are weak, from the `__mode` metafield
+  pub fn weak_mode(&self) -> (bool, bool) {
+    if let Value::String(mode) = self.get_meta("__mode") {
+      (mode.contains('k'), mode.contains('v'))
+    } else {
+      (false, false)
     }
   }
 
@@ -41,11 +71,23 @@ impl Table {
     Ok(self.tbl.borrow().get(idx).unwrap_or(&Value::Nil).clone())
   }
 
+  /// assigning nil removes the entry
   #[inline]
   pub fn insert(&self, idx: Value, val: Value) -> Result<(), RuntimeError> {
     self.validate_index(&idx)?;
 
-    self.tbl.borrow_mut().insert(idx, val);
+    // `t[1.0]` and `t[1]` are the same entry, keep the integer key
+    let idx = match idx {
+      Value::Number(..) => idx.to_integer().map(Value::Integer).unwrap_or(idx),
+      _ => idx
+    };
+
+    if val == Value::Nil {
+      self.tbl.borrow_mut().remove(&idx);
+    } else {
+      self.tbl.borrow_mut().insert(idx, val);
+    }
+
     Ok(())
   }
 
@@ -55,12 +97,28 @@ impl Table {
   }
 }
 
+impl Drop for Table {
+  fn drop(&mut self) {
+    if Rc::strong_count(&self.tbl) == 1 && !self.tbl.borrow().is_empty() {
+      let entries = self.tbl.borrow_mut().drain().flat_map(| (k, v) | [k, v]).collect();
+      drop_values(entries);
+    }
+  }
+}
+
 impl Debug for Table {
   fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
     write!(fmt, "{:?}", self.tbl.borrow())
   }
 }
 
+/// tables are compared by reference, `table.deep_equal` compares contents
+impl PartialEq for Table {
+  fn eq(&self, other: &Table) -> bool {
+    Rc::ptr_eq(&self.tbl, &other.tbl)
+  }
+}
+
 impl Hash for Table {
   fn hash<H>(&self, state: &mut H) where H: Hasher {
     Rc::as_ptr(&self.tbl).hash(state)
@@ -1,5 +1,5 @@
 use crate::parser::{ Parser, gen::Compiler };
-use crate::common::Closure;
+use crate::common::{ Closure, Value, Array };
 use crate::vm::VM;
 
 use std::io::prelude::*;
@@ -11,26 +11,32 @@ pub fn compile_file(name: String) -> Result<Closure, String> {
 
   file.read_to_string(&mut str).expect("failure to read file");
 
-  if str == "" {
-    let mut c = Closure::new(name);
-    c.name = "main".into();
-    return Ok(c)
-  }
+  let mut compiler = Compiler::new(name.clone());
 
-  let mut parser = Parser::new(str.into(), name.clone());
-  parser.parse()?;
+  // an empty file has nothing to parse
+  if !str.is_empty() {
+    let mut parser = Parser::new(str.into(), name);
+    parser.parse()?;
 
-  let mut compiler = Compiler::new(name.clone());
-  compiler.compile(parser.nodes)?;
+    compiler.compile(parser.nodes)?;
+  }
+
+  // files loaded with `require` give nil unless they return something
+  compiler.final_ret();
   compiler.closure.name = "main".into();
 
   Ok(compiler.closure)
 }
 
-pub fn do_file(name: String) -> Result<(), String> {
+pub fn do_file(name: String, max_depth: usize, args: Vec<String>) -> Result<(), String> {
   let closure = compile_file(name)?;
 
   let mut vm = VM::new(closure);
+  vm.set_max_depth(max_depth);
+
+  let args = args.into_iter().map(| arg | Value::String(arg.into())).collect();
+  vm.env.set_global(Value::String("arg".into()), Value::Array(Array::new(args)));
+
   vm.run()
 }
 
@@ -1,23 +1,25 @@
 use std::fmt::{ Debug, Formatter, Result as FmtResult };
-use std::ops::{ Add, Sub, Div, Mul, Rem };
+use std::ops::{ Add, Sub, Div, Mul, Rem, BitAnd, BitOr, BitXor, Shl, Shr };
 use std::cmp::{ PartialEq, Ordering };
 use std::hash::{ Hash, Hasher };
 use std::rc::Rc;
 
-use crate::common::{ Closure, Array, Table };
+use crate::common::{ Closure, Array, Table, Str, inspect, InspectOptions };
 use crate::vm::{ VM, RuntimeError };
 
 pub type BuiltIn = dyn Fn(&mut VM) -> Result<Value, RuntimeError>;
 
 pub struct RustFunc {
   pub name: String,
-  pub func: &'static BuiltIn
+  /// builtins are plain functions, iterators are closures that keep state
+  pub func: Rc<BuiltIn>
 }
 
-#[derive(Clone, PartialEq, PartialOrd)]
+#[derive(Clone)]
 pub enum Value {
-  String(String),
+  String(Str),
   Number(f64),
+  Integer(i64),
   Bool(bool),
   Closure(Closure),
   NativeFunc(Rc<RustFunc>),
@@ -42,14 +44,208 @@ impl Value {
     match self {
       Value::String(s) => s.to_string(),
       Value::Number(n) => n.to_string(),
+      Value::Integer(n) => n.to_string(),
       Value::Bool(b) => b.to_string(),
       Value::Closure(c) => format!("function: {}", c.name),
       Value::NativeFunc(rf) => format!("function: {}", rf.name),
-      Value::Array(array) => format!("{:?}", array.vec.borrow()),
-      Value::Table(t) => format!("{:?}", t.tbl.borrow()),
+      Value::Array(..) | Value::Table(..) => inspect(self, InspectOptions::default()),
       Value::Nil => "nil".into()
     }
   }
+
+  /// only nil and false are falsy
+  #[inline]
+  pub fn truthy(&self) -> bool {
+    !matches!(self, Value::Nil | Value::Bool(false))
+  }
+
+  /// converts integers to floats, returns `None` if the value isn't a number
+  #[inline]
+  pub fn to_float(&self) -> Option<f64> {
+    match self {
+      Value::Number(n) => Some(*n),
+      Value::Integer(n) => Some(*n as f64),
+      _ => None
+    }
+  }
+
+  /// returns the value as an integer if it's an integer or a float with an
+  /// exact integer representation
+  #[inline]
+  pub fn to_integer(&self) -> Option<i64> {
+    match self {
+      Value::Integer(n) => Some(*n),
+      Value::Number(n) => float_to_int(*n),
+      _ => None
+    }
+  }
+
+  /// integer division rounding towards minus infinity, `Reg[C] = RC[A] // RC[B]`
+  pub fn idiv(&self, rhs: &Value) -> Result<Value, RuntimeError> {
+    match (self, rhs) {
+      (Value::Integer(_), Value::Integer(0)) => Err("attempt to perform 'n//0'".into()),
+      (Value::Integer(lhs), Value::Integer(rhs)) => {
+        let div = lhs.wrapping_div(*rhs);
+
+        if (lhs.wrapping_rem(*rhs) != 0) && ((*lhs < 0) != (*rhs < 0)) {
+          Ok(Value::Integer(div - 1))
+        } else {
+          Ok(Value::Integer(div))
+        }
+      }
+
+      _ => float_arith(self, rhs, | lhs, rhs | (lhs / rhs).floor())
+    }
+  }
+
+  /// always returns a float like `/`, `Reg[C] = RC[A] ** RC[B]`
+  pub fn pow(&self, rhs: &Value) -> Result<Value, RuntimeError> {
+    float_arith(self, rhs, f64::powf)
+  }
+
+  /// bitwise not, `Reg[A] = ~RC[B]`
+  pub fn bnot(&self) -> Result<Value, RuntimeError> {
+    match (self.to_integer(), self) {
+      (Some(n), _) => Ok(Value::Integer(!n)),
+      (None, Value::Number(..)) => Err("number has no integer representation".into()),
+
+      _ => Err(RuntimeError::TypeError("perform a bitwise operation on".into(), Type::from(self), None))
+    }
+  }
+
+  /// index of the variant, used to order values of different types
+  fn order(&self) -> u8 {
+    match self {
+      Value::String(..) => 0,
+      Value::Number(..) | Value::Integer(..) => 1,
+      Value::Bool(..) => 2,
+      Value::Closure(..) => 3,
+      Value::NativeFunc(..) => 4,
+      Value::Array(..) => 5,
+      Value::Table(..) => 6,
+      Value::Nil => 7
+    }
+  }
+}
+
+/// returns `n` as an integer if it has no fractional part and fits in an `i64`
+fn float_to_int(n: f64) -> Option<i64> {
+  // 2^63 is exact as a float, `i64::MAX as f64` isn't
+  if n.fract() == 0.0 && (-9223372036854775808.0 .. 9223372036854775808.0).contains(&n) {
+    Some(n as i64)
+  } else {
+    None
+  }
+}
+
+/// compares an integer with a float without losing precision
+fn cmp_int_float(i: i64, f: f64) -> Option<Ordering> {
+  if f.is_nan() { return None }
+
+  if f >= 9223372036854775808.0 {
+    Some(Ordering::Less)
+  } else if f < -9223372036854775808.0 {
+    Some(Ordering::Greater)
+  } else {
+    let floor = f.floor();
+
+    match i.cmp(&(floor as i64)) {
+      Ordering::Equal if f > floor => Some(Ordering::Less),
+      ord => Some(ord)
+    }
+  }
+}
+
+fn float_arith(lhs: &Value, rhs: &Value, op: fn(f64, f64) -> f64) -> Result<Value, RuntimeError> {
+  match (lhs.to_float(), rhs.to_float()) {
+    (Some(lhs), Some(rhs)) => Ok(Value::Number(op(lhs, rhs))),
+    _ => Err(RuntimeError::TypeError("perform an arithmetic on".into(), Type::from(lhs), Some(Type::from(rhs))))
+  }
+}
+
+/// floats are converted to integers if they have an exact representation
+fn int_arith(lhs: &Value, rhs: &Value, op: fn(i64, i64) -> i64) -> Result<Value, RuntimeError> {
+  match (lhs.to_integer(), rhs.to_integer()) {
+    (Some(lhs), Some(rhs)) => Ok(Value::Integer(op(lhs, rhs))),
+    _ if lhs.to_float().is_some() && rhs.to_float().is_some() => {
+      Err("number has no integer representation".into())
+    }
+
+    _ => Err(RuntimeError::TypeError("perform a bitwise operation on".into(), Type::from(lhs), Some(Type::from(rhs))))
+  }
+}
+
+/// logical shift, shifting by a negative amount shifts the other way
+fn shift_left(lhs: i64, rhs: i64) -> i64 {
+  match rhs {
+    64 ..= i64::MAX | i64::MIN ..= -64 => 0,
+    0 ..= 63 => ((lhs as u64) << rhs) as i64,
+    _ => ((lhs as u64) >> -rhs) as i64
+  }
+}
+
+/// Drops the values one container at a time. Letting rust drop a deeply
+/// nested array or table recurses once per level and overflows the stack,
+/// so the containers that aren't shared are emptied into `stack` first.
+pub(crate) fn drop_values(mut stack: Vec<Value>) {
+  while let Some(val) = stack.pop() {
+    match &val {
+      Value::Array(a) if Rc::strong_count(&a.vec) == 1 => {
+        stack.append(&mut a.vec.borrow_mut());
+      }
+
+      Value::Table(t) => {
+        if Rc::strong_count(&t.tbl) == 1 {
+          stack.extend(t.tbl.borrow_mut().drain().flat_map(| (k, v) | [k, v]));
+        }
+
+        if Rc::strong_count(&t.meta) == 1 {
+          stack.extend(t.meta.borrow_mut().take().map(Value::Table));
+        }
+      }
+
+      _ => {}
+    }
+  }
+}
+
+impl PartialEq for Value {
+  fn eq(&self, rhs: &Value) -> bool {
+    match (self, rhs) {
+      (Value::String(lhs), Value::String(rhs)) => lhs == rhs,
+      (Value::Number(lhs), Value::Number(rhs)) => lhs == rhs,
+      (Value::Integer(lhs), Value::Integer(rhs)) => lhs == rhs,
+      (Value::Integer(i), Value::Number(f)) |
+        (Value::Number(f), Value::Integer(i)) => cmp_int_float(*i, *f) == Some(Ordering::Equal),
+      (Value::Bool(lhs), Value::Bool(rhs)) => lhs == rhs,
+      (Value::Closure(lhs), Value::Closure(rhs)) => Rc::ptr_eq(&lhs.upvals, &rhs.upvals),
+      (Value::NativeFunc(lhs), Value::NativeFunc(rhs)) => lhs == rhs,
+      (Value::Array(lhs), Value::Array(rhs)) => lhs == rhs,
+      (Value::Table(lhs), Value::Table(rhs)) => lhs == rhs,
+      (Value::Nil, Value::Nil) => true,
+
+      _ => false
+    }
+  }
+}
+
+impl PartialOrd for Value {
+  fn partial_cmp(&self, rhs: &Value) -> Option<Ordering> {
+    match (self, rhs) {
+      (Value::String(lhs), Value::String(rhs)) => lhs.partial_cmp(rhs),
+      (Value::Number(lhs), Value::Number(rhs)) => lhs.partial_cmp(rhs),
+      (Value::Integer(lhs), Value::Integer(rhs)) => lhs.partial_cmp(rhs),
+      (Value::Integer(lhs), Value::Number(rhs)) => cmp_int_float(*lhs, *rhs),
+      (Value::Number(lhs), Value::Integer(rhs)) => cmp_int_float(*rhs, *lhs).map(Ordering::reverse),
+      (Value::Bool(lhs), Value::Bool(rhs)) => lhs.partial_cmp(rhs),
+      (Value::Closure(lhs), Value::Closure(rhs)) => lhs.partial_cmp(rhs),
+      (Value::NativeFunc(lhs), Value::NativeFunc(rhs)) => lhs.partial_cmp(rhs),
+      (Value::Array(lhs), Value::Array(rhs)) => lhs.partial_cmp(rhs),
+      (Value::Table(lhs), Value::Table(rhs)) => lhs.partial_cmp(rhs),
+
+      _ => self.order().partial_cmp(&rhs.order())
+    }
+  }
 }
 
 impl Eq for Value {}
@@ -58,12 +254,17 @@ impl Hash for Value {
   fn hash<H>(&self, state: &mut H) where H: Hasher {
     match self {
       Value::String(s) => s.hash(state),
-      Value::Number(n) => n.to_bits().hash(state), // make sure `n != NaN`
+      // floats equal to an integer have to hash like that integer
+      Value::Number(n) => match float_to_int(*n) {
+        Some(n) => n.hash(state),
+        None => n.to_bits().hash(state) // make sure `n != NaN`
+      },
+      Value::Integer(n) => n.hash(state),
       Value::Bool(b) => b.hash(state),
 
       Value::Array(a) => a.hash(state),
       Value::Table(t) => t.hash(state),
-      Value::Closure(c) => (c as *const Closure).hash(state),
+      Value::Closure(c) => Rc::as_ptr(&c.upvals).hash(state),
       Value::NativeFunc(nf) => Rc::as_ptr(nf).hash(state),
 
       Value::Nil => panic!("cannot hash nil")
@@ -104,7 +305,7 @@ impl From<&Value> for Type {
     match val {
       Value::NativeFunc(..) | Value::Closure(..) => Type::Function,
       Value::String(..) => Type::String,
-      Value::Number(..) => Type::Number,
+      Value::Number(..) | Value::Integer(..) => Type::Number,
       Value::Bool(..) => Type::Bool,
       Value::Array(..) => Type::Array,
       Value::Table(..) => Type::Table,
@@ -113,66 +314,121 @@ impl From<&Value> for Type {
   }
 }
 
-impl Add for Value {
-  fn add(self, rhs: Value) -> Result<Value, ()> {
-    match (self.clone(), rhs.clone()) {
-      (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Number(lhs + rhs)),
-      (Value::String(lhs), rhs @ _) => Ok(Value::String(lhs + rhs.to_string().as_str())),
-      (lhs @ _, Value::String(rhs)) => Ok(Value::String(lhs.to_string().as_str().to_owned() + &rhs)),
+impl Add for &Value {
+  fn add(self, rhs: &Value) -> Result<Value, RuntimeError> {
+    match (self, rhs) {
+      (Value::Integer(lhs), Value::Integer(rhs)) => Ok(Value::Integer(lhs.wrapping_add(*rhs))),
+      (Value::String(..), _) | (_, Value::String(..)) => {
+        let mut str = self.to_string();
+        str.push_str(&rhs.to_string());
+
+        Ok(Value::String(str.into()))
+      }
 
-      _ => Err(())
+      _ => float_arith(self, rhs, | lhs, rhs | lhs + rhs)
     }
   }
 
-  type Output = Result<Value, ()>;
+  type Output = Result<Value, RuntimeError>;
 }
 
-impl Sub for Value {
-  fn sub(self, rhs: Value) -> Result<Value, ()> {
-    match (self.clone(), rhs.clone()) {
-      (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Number(lhs - rhs)),
+impl Sub for &Value {
+  fn sub(self, rhs: &Value) -> Result<Value, RuntimeError> {
+    match (self, rhs) {
+      (Value::Integer(lhs), Value::Integer(rhs)) => Ok(Value::Integer(lhs.wrapping_sub(*rhs))),
 
-      _ => Err(())
+      _ => float_arith(self, rhs, | lhs, rhs | lhs - rhs)
     }
   }
 
-  type Output = Result<Value, ()>;
+  type Output = Result<Value, RuntimeError>;
 }
 
-impl Mul for Value {
-  fn mul(self, rhs: Value) -> Result<Value, ()> {
-    match (self.clone(), rhs.clone()) {
-      (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Number(lhs * rhs)),
+impl Mul for &Value {
+  fn mul(self, rhs: &Value) -> Result<Value, RuntimeError> {
+    match (self, rhs) {
+      (Value::Integer(lhs), Value::Integer(rhs)) => Ok(Value::Integer(lhs.wrapping_mul(*rhs))),
 
-      _ => Err(())
+      _ => float_arith(self, rhs, | lhs, rhs | lhs * rhs)
     }
   }
 
-  type Output = Result<Value, ()>;
+  type Output = Result<Value, RuntimeError>;
 }
 
-impl Div for Value {
-  fn div(self, rhs: Value) -> Result<Value, ()> {
-    match (self.clone(), rhs.clone()) {
-      (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Number(lhs / rhs)),
+/// always returns a float, use `Value::idiv` for integer division
+impl Div for &Value {
+  fn div(self, rhs: &Value) -> Result<Value, RuntimeError> {
+    float_arith(self, rhs, | lhs, rhs | lhs / rhs)
+  }
+
+  type Output = Result<Value, RuntimeError>;
+}
 
-      _ => Err(())
+/// floored like `//`, the result has the sign of the divisor
+impl Rem for &Value {
+  fn rem(self, rhs: &Value) -> Result<Value, RuntimeError> {
+    match (self, rhs) {
+      (Value::Integer(_), Value::Integer(0)) => Err("attempt to perform 'n%0'".into()),
+      (Value::Integer(lhs), Value::Integer(rhs)) => {
+        let rem = lhs.wrapping_rem(*rhs);
+
+        if rem != 0 && (rem < 0) != (*rhs < 0) {
+          Ok(Value::Integer(rem + rhs))
+        } else {
+          Ok(Value::Integer(rem))
+        }
+      }
+
+      _ => float_arith(self, rhs, | lhs, rhs | {
+        let rem = lhs % rhs;
+
+        if rem != 0.0 && (rem < 0.0) != (rhs < 0.0) { rem + rhs } else { rem }
+      })
     }
   }
 
-  type Output = Result<Value, ()>;
+  type Output = Result<Value, RuntimeError>;
 }
 
-impl Rem for Value {
-  fn rem(self, rhs: Value) -> Result<Value, ()> {
-    match (self.clone(), rhs.clone()) {
-      (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Number(lhs % rhs)),
+impl BitAnd for &Value {
+  fn bitand(self, rhs: &Value) -> Result<Value, RuntimeError> {
+    int_arith(self, rhs, | lhs, rhs | lhs & rhs)
+  }
 
-      _ => Err(())
-    }
+  type Output = Result<Value, RuntimeError>;
+}
+
+impl BitOr for &Value {
+  fn bitor(self, rhs: &Value) -> Result<Value, RuntimeError> {
+    int_arith(self, rhs, | lhs, rhs | lhs | rhs)
+  }
+
+  type Output = Result<Value, RuntimeError>;
+}
+
+impl BitXor for &Value {
+  fn bitxor(self, rhs: &Value) -> Result<Value, RuntimeError> {
+    int_arith(self, rhs, | lhs, rhs | lhs ^ rhs)
+  }
+
+  type Output = Result<Value, RuntimeError>;
+}
+
+impl Shl for &Value {
+  fn shl(self, rhs: &Value) -> Result<Value, RuntimeError> {
+    int_arith(self, rhs, shift_left)
+  }
+
+  type Output = Result<Value, RuntimeError>;
+}
+
+impl Shr for &Value {
+  fn shr(self, rhs: &Value) -> Result<Value, RuntimeError> {
+    int_arith(self, rhs, | lhs, rhs | shift_left(lhs, rhs.wrapping_neg()))
   }
 
-  type Output = Result<Value, ()>;
+  type Output = Result<Value, RuntimeError>;
 }
 
 impl Debug for RustFunc {
@@ -1,4 +1,4 @@
-use crate::lexer::Token;
+use crate::lexer::{ Token, parse_number };
 
 pub struct Lexer {
   src: Vec<char>,
@@ -7,7 +7,9 @@ pub struct Lexer {
   pub pos: usize,
   pub line: usize,
   pub buf: String,
-  pub token: Token
+  pub token: Token,
+  /// open braces inside each `${` of the interpolated strings being read
+  templates: Vec<usize>
 }
 
 fn resolve(res: Result<(), String>, token: Token) -> Result<Token, String> {
@@ -29,6 +31,7 @@ impl Lexer {
       line: 1,
       buf: String::new(),
       token: Token::None,
+      templates: Vec::new(),
       current,
     }
   }
@@ -69,16 +72,38 @@ impl Lexer {
       ')' => next_ret!(Token::RightParen),
       '[' => next_ret!(Token::LeftSquare),
       ']' => next_ret!(Token::RightSquare),
-      '{' => next_ret!(Token::LeftBrace),
-      '}' => next_ret!(Token::RightBrace),
+      '{' => {
+        if let Some(depth) = self.templates.last_mut() { *depth += 1 }
+        next_ret!(Token::LeftBrace)
+      }
+
+      '}' => match self.templates.last_mut() {
+        // the end of a `${...}`, go back to the string
+        Some(0) => {
+          self.templates.pop();
+          self.next();
+          self.read_template()
+        }
+
+        Some(depth) => {
+          *depth -= 1;
+          next_ret!(Token::RightBrace)
+        }
+
+        None => next_ret!(Token::RightBrace)
+      },
       '+' => next_ret!(Token::Plus),
       '-' => next_ret!(Token::Dash),
+      '*' if self.peek() == '*' => { self.next(); next_ret!(Token::DoubleStar) },
       '*' => next_ret!(Token::Star),
       ';' => next_ret!(Token::Semi),
       ',' => next_ret!(Token::Comma),
+      '.' if self.peek().is_ascii_digit() && !self.ends_expr() => self.read_number(),
       '.' => next_ret!(Token::Dot),
       ':' => next_ret!(Token::Colon),
       '%' => next_ret!(Token::Percent),
+      '^' => next_ret!(Token::Caret),
+      '~' => next_ret!(Token::Tilde),
 
       '&' => {
         self.next();
@@ -86,7 +111,7 @@ impl Lexer {
           self.next();
           Ok(Token::And)
         } else {
-          Err(self.error_near("unexpected token", Token::SC('&')))
+          Ok(Token::Amp)
         }
       }
 
@@ -96,28 +121,50 @@ impl Lexer {
           self.next();
           Ok(Token::Or)
         } else {
+          // either a bitwise or or the start of an anonymous function,
+          // the parser decides
           Ok(Token::Line)
         }
       }
 
       '/' => {
         self.next();
-        if self.current == '/' || self.current == '*' {
-          self.comment()?;
-          self.lex()
-        } else {
-          return Ok(Token::Slash)
+        match self.current {
+          '*' => {
+            self.comment()?;
+            self.lex()
+          }
+
+          '/' => next_ret!(Token::DoubleSlash),
+          _ => Ok(Token::Slash)
         }
       },
 
+      '#' => {
+        self.comment()?;
+        self.lex()
+      }
+
       '=' => cmp_op!(Token::Equal, Token::Eq),
+      '<' if self.peek() == '<' => { self.next(); next_ret!(Token::Shl) },
+      '>' if self.peek() == '>' => { self.next(); next_ret!(Token::Shr) },
       '<' => cmp_op!(Token::Gt, Token::Ge),
       '>' => cmp_op!(Token::Lt, Token::Le),
       '!' => cmp_op!(Token::Bang, Token::Neq),
 
       '\0' => Ok(Token::Eof),
 
-      '\'' | '"' => resolve(self.read_string(self.current), Token::String),
+      '\'' | '"' => resolve(self.read_string(false), Token::String),
+
+      '`' => {
+        self.next();
+        self.read_template()
+      }
+
+      'r' if matches!(self.peek(), '\'' | '"') => {
+        self.next();
+        resolve(self.read_string(true), Token::String)
+      }
 
 
       '\n' => {
@@ -141,7 +188,7 @@ impl Lexer {
             Ok(Token::Name)
           }
         } else if self.is_num() {
-          resolve(self.read_number(), Token::Number)
+          self.read_number()
         } else {
           Err(self.error_near("unexpected token", Token::SC(self.current)))
         }
@@ -166,11 +213,10 @@ impl Lexer {
         }
       },
 
-      '/' => {
+      '#' => {
         while self.current != '\n' && self.current != '\0' {
           self.next();
         }
-        self.next();
         Ok(())
       },
 
@@ -188,79 +234,231 @@ impl Lexer {
     }
   }
 
-  fn read_string(&mut self, quote: char) -> Result<(), String> {
-    self.buf.push(self.current);
+  /// Reads a string delimited by the current quote. Strings in triple quotes
+  /// can span multiple lines, and raw strings (`r"..."`) keep backslashes as
+  /// they are, though `\"` doesn't end them.
+  fn read_string(&mut self, raw: bool) -> Result<(), String> {
+    let quote = self.current;
+    let long = self.peek() == quote && self.peek_nth(2) == quote;
+
+    self.buf.push(quote);
     self.next();
 
-    while self.current != quote {
+    if long {
+      self.next();
+      self.next();
+
+      // a newline right after the opening quotes isn't part of the string
+      if self.current == '\n' {
+        self.line += 1;
+        self.next();
+      }
+    }
+
+    loop {
       match self.current {
-        c @ ('\0' | '\n') => {
-          let tkn = if c == '\0' { Token::Eof } else { Token::String };
+        '\0' => return Err(self.error_near("unfinished string", Token::Eof)),
+
+        c if c == quote && (!long || (self.peek() == quote && self.peek_nth(2) == quote)) => break,
 
-          return Err(self.error_near("unfinished string", tkn))
+        '\n' if !long => return Err(self.error_near("unfinished string", Token::String)),
+
+        '\n' => {
+          self.line += 1;
+          self.buf.push('\n');
+          self.next();
         }
 
-        '\\' => {
+        '\\' if !raw => self.read_escape()?,
+
+        // a backslash still keeps a quote from ending a raw string
+        '\\' if matches!(self.peek(), '\'' | '"' | '\\') => {
+          self.buf.push('\\');
+          self.next();
+          self.buf.push(self.current);
           self.next();
+        }
 
-          let c = match self.current {
-            '\n' => {
-              self.line += 1;
-              self.next();
-              ""
-            }
+        c => {
+          self.buf.push(c);
+          self.next();
+        }
+      }
+    }
+
+    for _ in 0 .. if long { 3 } else { 1 } {
+      self.next();
+    }
 
-            'n' | 'r' => "\n",
-            't' => "\t",
-            '\'' => "'",
-            '"' => "\"",
-            '\\' => "\\",
-            '\0' => continue,
+    self.buf.remove(0);
+    Ok(())
+  }
 
-            _ => {
-              return Err(self.error_near("invalid escape", Token::String))
-            }
-          };
+  /// Reads an interpolated string up to the next `${` or the closing
+  /// backtick. The parser reads the expression after a `Token::Template` and
+  /// the `}` that ends it brings the lexer back here.
+  fn read_template(&mut self) -> Result<Token, String> {
+    loop {
+      match self.current {
+        '\0' => return Err(self.error_near("unfinished string", Token::Eof)),
 
+        '`' => {
           self.next();
-          self.buf += c;
+          return Ok(Token::TemplateEnd)
         }
 
-        _ => {
-          self.buf.push(self.current);
+        '$' if self.peek() == '{' => {
+          self.next();
+          self.next();
+          self.templates.push(0);
+
+          return Ok(Token::Template)
+        }
+
+        '\n' => {
+          self.line += 1;
+          self.buf.push('\n');
+          self.next();
+        }
+
+        '\\' => self.read_escape()?,
+
+        c => {
+          self.buf.push(c);
           self.next();
         }
       }
     }
+  }
 
+  /// `\u{N...}` is a code point and `\xNN` a byte, a run of `\xNN` has to
+  /// be valid UTF-8 like the bytes given to `string.char`
+  fn read_escape(&mut self) -> Result<(), String> {
     self.next();
-    self.buf = self.buf[1..].to_string();
-    Ok(())
-  }
 
-  fn read_number(&mut self) -> Result<(), String> {
-    macro_rules! get_num_char {
-      () => {
-        while self.is_num() || self.current == '_' {
-          if self.current == '_' { self.next(); continue }
-          self.buf.push(self.current);
+    let c = match self.current {
+      '\n' => {
+        self.line += 1;
+        self.next();
+        return Ok(())
+      }
+
+      'n' => '\n',
+      'r' => '\r',
+      't' => '\t',
+      '0' => '\0',
+      '\'' => '\'',
+      '"' => '"',
+      '\\' => '\\',
+      '`' => '`',
+      '$' => '$',
+
+      'x' => {
+        let mut bytes = Vec::new();
+
+        loop {
+          self.next();
+          bytes.push(self.read_hex(2, 2)? as u8);
+          self.next();
+
+          if self.current != '\\' || self.peek() != 'x' { break }
           self.next();
         }
-      };
-    }
 
-    get_num_char!();
-    if self.current == '.' {
-      self.buf.push(self.current);
+        match std::str::from_utf8(&bytes) {
+          Ok(str) => self.buf.push_str(str),
+          Err(..) => return Err(self.error_near("invalid UTF-8 in \\x escapes, use \\u{xx} for a code point", Token::String))
+        }
+
+        return Ok(())
+      }
+
+      'u' => {
+        self.next();
+
+        if self.current != '{' {
+          return Err(self.error_near("missing '{' in \\u{xxxx}", Token::String))
+        }
+
+        self.next();
+        let code = self.read_hex(1, 6)?;
+        self.next();
+
+        if self.current != '}' {
+          return Err(self.error_near("missing '}' in \\u{xxxx}", Token::String))
+        }
+
+        char::from_u32(code).ok_or_else(|| self.error_near("invalid unicode code point", Token::String))?
+      }
+
+      // the caller reports the unfinished string
+      '\0' => return Ok(()),
+
+      _ => return Err(self.error_near("invalid escape", Token::String))
+    };
+
+    self.next();
+    self.buf.push(c);
+    Ok(())
+  }
+
+  /// reads between `min` and `max` hex digits and stops on the last one
+  fn read_hex(&mut self, min: usize, max: usize) -> Result<u32, String> {
+    let mut code = 0;
+    let mut len = 0;
+
+    while let Some(digit) = self.current.to_digit(16) {
+      code = code * 16 + digit;
+      len += 1;
+
+      if len == max || !self.peek().is_ascii_hexdigit() { break }
       self.next();
-      get_num_char!();
     }
 
-    if self.is_alnum() {
-      Err(self.error_near("malformed number", self.token))
+    if len < min {
+      Err(self.error_near("hexadecimal digit expected", Token::String))
     } else {
-      Ok(())
+      Ok(code)
+    }
+  }
+
+  /// reads anything that looks like a number and checks it with `parse_number`
+  fn read_number(&mut self) -> Result<Token, String> {
+    loop {
+      let hex = self.buf.starts_with("0x") || self.buf.starts_with("0X");
+      let exp_sign = matches!(self.current, '+' | '-') && !hex && self.buf.ends_with(&['e', 'E'][..]);
+
+      if self.is_alnum() || matches!(self.current, '_' | '.') || exp_sign {
+        self.buf.push(self.current);
+        self.next();
+      } else {
+        break
+      }
     }
+
+    if let Err(e) = parse_number(&self.buf) {
+      Err(self.error_near(&format!("malformed number ({})", e), Token::Number))
+    } else {
+      Ok(Token::Number)
+    }
+  }
+
+  /// returns true if the last token can end an expression, so a `.` after it
+  /// is an index like `t.1` and not a number like `.5`
+  fn ends_expr(&self) -> bool {
+    matches!(self.token,
+      Token::Name | Token::Number | Token::String | Token::Nil | Token::True | Token::False |
+      Token::RightParen | Token::RightSquare | Token::RightBrace)
+  }
+
+  #[inline]
+  fn peek(&self) -> char {
+    self.peek_nth(1)
+  }
+
+  #[inline]
+  fn peek_nth(&self, n: usize) -> char {
+    *self.src.get(self.pos + n).unwrap_or(&'\0')
   }
 
   fn next(&mut self) {
@@ -337,7 +535,9 @@ impl Lexer {
       Token::Plus => "+",
       Token::Dash => "-",
       Token::Star => "*",
+      Token::DoubleStar => "**",
       Token::Slash => "/",
+      Token::DoubleSlash => "//",
       Token::Bang => "!",
       Token::Semi => ";",
       Token::Comma => ",",
@@ -348,6 +548,13 @@ impl Lexer {
       Token::Lt => ">",
       Token::Le => ">=",
       Token::Line => "|",
+      Token::Template => "${",
+      Token::TemplateEnd => "`",
+      Token::Amp => "&",
+      Token::Caret => "^",
+      Token::Tilde => "~",
+      Token::Shl => "<<",
+      Token::Shr => ">>",
       Token::Dot => ".",
       Token::Colon => ":",
 
@@ -1,5 +1,7 @@
 mod token;
 mod lexer;
+mod number;
 
 pub use token::Token;
-pub use lexer::Lexer;
\ No newline at end of file
+pub use lexer::Lexer;
+pub use number::parse_number;
\ No newline at end of file
@@ -0,0 +1,82 @@
+use crate::common::Value;
+
+/// Parses a number literal without its sign: decimal integers, floats with an
+/// optional exponent like `1.5e-3` or `.5`, and `0x`, `0o` and `0b` integers.
+/// Underscores can be used anywhere to separate digits.
+///
+/// Decimal integers too big for an `i64` become floats while the other bases
+/// wrap around, so `0xffffffffffffffff` is `-1`. `tonumber` uses this too so
+/// source and runtime parsing agree.
+pub fn parse_number(s: &str) -> Result<Value, String> {
+  let s = s.replace('_', "").to_ascii_lowercase();
+
+  let (radix, name) = match s.get(.. 2) {
+    Some("0x") => (16, "hex"),
+    Some("0o") => (8, "octal"),
+    Some("0b") => (2, "binary"),
+    _ => return parse_decimal(&s)
+  };
+
+  let digits = &s[2 ..];
+
+  if digits.is_empty() {
+    return Err(format!("missing {} digits", name))
+  }
+
+  let mut n: i64 = 0;
+
+  for c in digits.chars() {
+    let digit = c.to_digit(radix).ok_or_else(|| format!("invalid {} digit '{}'", name, c))?;
+    n = n.wrapping_mul(radix.into()).wrapping_add(digit.into());
+  }
+
+  Ok(Value::Integer(n))
+}
+
+fn parse_decimal(s: &str) -> Result<Value, String> {
+  let (mantissa, exp) = match s.find('e') {
+    Some(i) => (&s[.. i], Some(&s[i + 1 ..])),
+    None => (s, None)
+  };
+
+  let (int, frac) = match mantissa.find('.') {
+    Some(i) => (&mantissa[.. i], Some(&mantissa[i + 1 ..])),
+    None => (mantissa, None)
+  };
+
+  check_digits(int)?;
+  check_digits(frac.unwrap_or_default())?;
+
+  if int.is_empty() && frac.unwrap_or_default().is_empty() {
+    return Err("missing digits".into())
+  }
+
+  if let Some(exp) = exp {
+    let digits = exp.strip_prefix(&['+', '-'][..]).unwrap_or(exp);
+
+    if digits.is_empty() {
+      return Err("missing exponent digits".into())
+    }
+
+    check_digits(digits)?;
+  } else if frac.is_none() {
+    if let Ok(n) = int.parse() {
+      return Ok(Value::Integer(n))
+    }
+  }
+
+  let int = if int.is_empty() { "0" } else { int };
+  let frac = frac.filter(| f | !f.is_empty()).unwrap_or("0");
+
+  // every part was checked so this can't fail
+  let n = format!("{}.{}e{}", int, frac, exp.unwrap_or("0")).parse().unwrap();
+
+  Ok(Value::Number(n))
+}
+
+fn check_digits(s: &str) -> Result<(), String> {
+  match s.chars().find(| c | !c.is_ascii_digit()) {
+    Some(c) => Err(format!("invalid digit '{}'", c)),
+    None => Ok(())
+  }
+}
@@ -1,6 +1,10 @@
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Token {
   String,
+  /// the text of an interpolated string before a `${`
+  Template,
+  /// the text of an interpolated string after the last `}`
+  TemplateEnd,
   Number,
   Name,
   /// default token
@@ -32,8 +36,15 @@ pub enum Token {
   Plus,
   Dash,
   Star,
+  DoubleStar,
   Slash,
+  DoubleSlash,
   Percent,
+  Amp,
+  Caret,
+  Tilde,
+  Shl,
+  Shr,
   Bang,
   Semi,
   Comma,
@@ -13,7 +13,7 @@ mod vm;
 enum Exec {
   PrintBytecodeRecursive(String),
   PrintBytecode(String),
-  DoFile(String),
+  DoFile(String, usize, Vec<String>),
   Exit
 }
 
@@ -26,34 +26,48 @@ fn get_name(name: Option<&String>, err: &str) -> Result<String, String> {
 }
 
 fn parse_args(args: Vec<String>) -> Result<Exec, String> {
-  match args.get(1) {
-    Some(v) => match v.trim() {
+  let mut max_depth = vm::MAX_DEPTH;
+  let mut i = 1;
+
+  while let Some(v) = args.get(i) {
+    match v.trim() {
 
       "-l" => {
-        let name = get_name(args.get(2), "expected file name")?;
-        Ok(Exec::PrintBytecode(name.trim().to_string()))
+        let name = get_name(args.get(i + 1), "expected file name")?;
+        return Ok(Exec::PrintBytecode(name.trim().to_string()))
       },
 
       "-ll" => {
-        let name = get_name(args.get(2), "expected file name")?;
-        Ok(Exec::PrintBytecodeRecursive(name.trim().to_string()))
+        let name = get_name(args.get(i + 1), "expected file name")?;
+        return Ok(Exec::PrintBytecodeRecursive(name.trim().to_string()))
       }
 
-      _ => {
-        Ok(Exec::DoFile(v.to_string()))
+      "--max-depth" => {
+        let depth = get_name(args.get(i + 1), "expected max depth")?;
+
+        max_depth = match depth.trim().parse() {
+          Ok(n) if n > 0 => n,
+          _ => return Err(format!("invalid max depth '{}'", depth))
+        };
+
+        i += 2;
       }
 
-    },
+      // everything after the file name is passed to the script as `arg`
+      _ => {
+        return Ok(Exec::DoFile(v.to_string(), max_depth, args[i + 1 ..].to_vec()))
+      }
 
-    None => {
-      println!("{} [options] filename
-      Options:
-          -l   print bytecode of main function
-          -ll  print bytecode of main function and all sub functions
-      ", args.get(0).unwrap_or(&"moon".to_string()));
-      Ok(Exec::Exit)
     }
   }
+
+  println!("{} [options] filename [args...]
+  Options:
+      -l               print bytecode of main function
+      -ll              print bytecode of main function and all sub functions
+      --max-depth n    set the maximum call depth (default {})
+  ", args.get(0).unwrap_or(&"moon".to_string()), vm::MAX_DEPTH);
+  Ok(Exec::Exit)
 }
 
 fn run() -> Result<(), String> {
@@ -61,8 +75,8 @@ fn run() -> Result<(), String> {
   let exec = parse_args(args)?;
 
   match exec {
-    Exec::DoFile(name) => {
-      do_file(name)
+    Exec::DoFile(name, max_depth, args) => {
+      do_file(name, max_depth, args)
     }
 
     Exec::PrintBytecode(name) => {
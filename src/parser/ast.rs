@@ -17,7 +17,14 @@ pub enum BinOp {
   Sub,
   Mul,
   Div,
+  IDiv,
   Mod,
+  Pow,
+  BAnd,
+  BOr,
+  BXor,
+  Shl,
+  Shr,
   And,
   Or
 }
@@ -25,13 +32,17 @@ pub enum BinOp {
 #[derive(Debug, PartialEq, Clone)]
 pub enum UnOp {
   Neg,
-  Not
+  Not,
+  BNot
 }
 
 #[derive(Debug, Clone)]
 pub enum Expr {
   String(String),
+  /// interpolated string, the parts are concatenated
+  Template(Vec<Expr>),
   Number(f64),
+  Integer(i64),
   Name(String),
   AnonFn(Vec<String>, Box<Node>),
   Index(Box<Expr>, Box<Expr>),
@@ -68,8 +79,13 @@ impl Expr {
 impl BinOp {
   pub fn priority(&self) -> u8 {
     match self {
-      BinOp::Mul | BinOp::Div | BinOp::Mod => 7,
-      BinOp::Add | BinOp::Sub => 6,
+      BinOp::Pow => 12,
+      BinOp::Mul | BinOp::Div | BinOp::IDiv | BinOp::Mod => 11,
+      BinOp::Add | BinOp::Sub => 10,
+      BinOp::Shl | BinOp::Shr => 9,
+      BinOp::BAnd => 8,
+      BinOp::BXor => 7,
+      BinOp::BOr => 6,
       BinOp::Gt | BinOp::Ge |
         BinOp::Lt | BinOp::Le => 5,
       BinOp::Eq | BinOp::Neq => 4,
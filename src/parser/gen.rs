@@ -1,4 +1,5 @@
 use std::convert::TryInto;
+use std::mem::discriminant;
 
 use crate::vm::code::get_op;
 use crate::common::{ Closure, Opcode, Value };
@@ -95,7 +96,7 @@ impl Compiler {
     self.error(err)
   }
 
-  fn final_ret(&mut self) {
+  pub fn final_ret(&mut self) {
     if get_op(*self.closure.code.last().unwrap_or(&0)) != Opcode::Return {
       self.emit(make_abc(Opcode::Return, 0, 1, 0))
     }
@@ -117,9 +118,8 @@ impl Compiler {
 
   #[inline]
   fn _walk(&mut self, node: Node, should_close: bool) -> Result<(), String> {
-    self.stmt(node.stmt, should_close)?;
     self.line = node.line;
-    Ok(())
+    self.stmt(node.stmt, should_close)
   }
 
   fn stmt(&mut self, stmt: Stmt, should_close: bool) -> Result<(), String> {
@@ -145,7 +145,8 @@ impl Compiler {
 
   fn fn_stmt(&mut self, name: String, params: Vec<String>, body: Node) -> Result<(), String> {
     let var = self.register_var(name.clone())?;
-    let closure = self.func_body(body, params)?;
+    let mut closure = self.func_body(body, params)?;
+    closure.name = name;
 
     self.load_closure(closure, var)?;
 
@@ -153,6 +154,16 @@ impl Compiler {
   }
 
   fn return_stmt(&mut self, val: Expr) -> Result<(), String> {
+    if let Expr::Call(func, args) = val {
+      self.reserve_regs(1)?;
+      let reg = self.freereg - 1;
+
+      self.call(*func, args, reg, Opcode::TailCall)?;
+      self.emit(make_abc(Opcode::Return, reg.into(), 0, 0));
+
+      return Ok(())
+    }
+
     let val = self.rc2nextreg(val)?;
 
     self.emit(make_abc(Opcode::Return, val.into(), 0, 0));
@@ -234,7 +245,7 @@ impl Compiler {
 
     self.walk(block)?;
 
-    self.closure.code[jmp_pos] = self.jmp(false, self.ni - top + 2)?;
+    self.fix_jmp(jmp_pos, false, self.ni - top + 1)?;
 
     let jmp = self.jmp(true, self.ni - start)?;
     self.emit(jmp);
@@ -302,12 +313,14 @@ impl Compiler {
 
   fn expr(&mut self, exp: Expr, reg: u8) -> Result<(), String> {
     match exp {
-      Expr::String(s) => self.load_const(Value::String(s), reg),
+      Expr::String(s) => self.load_const(Value::String(s.into()), reg),
       Expr::Number(n) => self.load_const(Value::Number(n), reg),
+      Expr::Integer(n) => self.load_const(Value::Integer(n), reg),
       Expr::Name(s) => self.load_var(s, reg),
       Expr::AnonFn(params, body) => self.load_func(params, *body, reg),
       Expr::Array(a) => self.load_array(a, reg),
       Expr::Table(tbl) => self.load_table(tbl, reg),
+      Expr::Template(parts) => self.load_template(parts, reg),
       Expr::Index(obj, idx) => self.load_index(*obj, *idx, reg),
 
       Expr::Bool(b) => Ok(self.load_bool(b, reg)),
@@ -315,15 +328,15 @@ impl Compiler {
 
       Expr::Binary(lhs, op, rhs) => self.binary(*lhs, op, *rhs, reg),
       Expr::Unary(op, exp) => self.unary(op, *exp, reg),
-      Expr::Call(func, args) => self.call(*func, args, reg),
+      Expr::Call(func, args) => self.call(*func, args, reg, Opcode::Call),
     }
   }
 
   fn load_table(&mut self, tbl: Vec<(Expr, Expr)>, reg: u8) -> Result<(), String> {
     let mut nexp = 0;
-    let reg = reg.into();
 
-    self.freeexp();
+    // the pairs are put in the registers starting at `reg`
+    self.freereg = reg;
 
     for (key, value) in tbl {
       self.exp2nextreg(key)?;
@@ -332,12 +345,29 @@ impl Compiler {
       nexp += 2;
     }
 
-    self.freereg -= if nexp == 0 { 0 } else { nexp - 1 };
+    self.freereg = reg + 1;
 
+    let reg = reg.into();
     self.emit(make_abc(Opcode::NewTable, reg, reg + nexp as u16, 0));
     Ok(())
   }
 
+  fn load_template(&mut self, parts: Vec<Expr>, reg: u8) -> Result<(), String> {
+    let mut nparts = 0;
+    self.freereg = reg;
+
+    for part in parts {
+      self.exp2nextreg(part)?;
+      nparts += 1;
+    }
+
+    self.freereg = reg + 1;
+
+    let reg = reg.into();
+    self.emit(make_abc(Opcode::Concat, reg, reg + nparts as u16, 0));
+    Ok(())
+  }
+
   fn load_func(&mut self, params: Vec<String>, body: Node, reg: u8) -> Result<(), String> {
     let closure = self.func_body(body, params)?;
     self.load_closure(closure, reg)?;
@@ -347,16 +377,16 @@ impl Compiler {
 
   fn load_array(&mut self, array: Vec<Expr>, reg: u8) -> Result<(), String> {
     let mut nelem = 0;
-    let reg = reg.into();
-    self.freeexp();
+    self.freereg = reg;
 
     for elem in array {
       self.exp2nextreg(elem)?;
       nelem += 1;
     }
 
-    self.freereg -= if nelem == 0 { 0 } else { nelem - 1 };
+    self.freereg = reg + 1;
 
+    let reg = reg.into();
     self.emit(make_abc(Opcode::NewArray, reg, reg + nelem as u16, 0));
     Ok(())
   }
@@ -371,9 +401,12 @@ impl Compiler {
     Ok(())
   }
 
-  fn call(&mut self, func: Expr, args: Vec<Expr>, reg: u8) -> Result<(), String> {
+  fn call(&mut self, func: Expr, args: Vec<Expr>, reg: u8, op: Opcode) -> Result<(), String> {
     self.expr(func, reg)?;
 
+    // arguments go right after the function
+    self.freereg = reg + 1;
+
     let mut narg = 0;
     let mut freg = self.freereg;
     self.reserve_regs(1)?;
@@ -386,8 +419,10 @@ impl Compiler {
       self.reserve_regs(1)?;
     }
 
+    self.freereg = reg + 1;
+
     let func_reg = reg as u16;
-    self.emit(make_abc(Opcode::Call, func_reg, func_reg + narg + 1, reg));
+    self.emit(make_abc(op, func_reg, func_reg + narg + 1, reg));
     Ok(())
   }
 
@@ -419,7 +454,14 @@ impl Compiler {
       BinOp::Sub => arith!(Sub),
       BinOp::Mul => arith!(Mul),
       BinOp::Div => arith!(Div),
+      BinOp::IDiv => arith!(IDiv),
       BinOp::Mod => arith!(Mod),
+      BinOp::Pow => arith!(Pow),
+      BinOp::BAnd => arith!(BAnd),
+      BinOp::BOr => arith!(BOr),
+      BinOp::BXor => arith!(BXor),
+      BinOp::Shl => arith!(Shl),
+      BinOp::Shr => arith!(Shr),
 
       BinOp::Neq => cmp!(Neq),
       BinOp::Eq => cmp!(Eq),
@@ -445,6 +487,10 @@ impl Compiler {
       UnOp::Not => {
         self.emit(make_abc(Opcode::Not, reg.into(), exp, 0))
       }
+
+      UnOp::BNot => {
+        self.emit(make_abc(Opcode::BNot, reg.into(), exp, 0))
+      }
     }
 
     Ok(())
@@ -462,7 +508,7 @@ impl Compiler {
 
     self.expr(rhs, reg)?;
 
-    self.closure.code[jmp_pos] = self.jmp(false, self.ni - start + 1)?;
+    self.fix_jmp(jmp_pos, false, self.ni - start)?;
 
     Ok(())
   }
@@ -476,7 +522,7 @@ impl Compiler {
       } else if let Some(upval) = self.get_upval(var.clone()) {
         make_abc(Opcode::SetUpVal, upval.into(), reg.into(), 0)
       } else {
-        let pos = self.resolve_const(Value::String(var))?;
+        let pos = self.resolve_const(Value::String(var.into()))?;
         make_abx(Opcode::SetGlobal, reg.into(), pos)
       };
 
@@ -506,7 +552,7 @@ impl Compiler {
     macro_rules! RC {
       ($i:ident, $v:ident) => {
         {
-          let pos = self.resolve_const(Value::$i($v))?;
+          let pos = self.resolve_const(Value::$i($v.into()))?;
 
           if pos < u8::MAX.into() {
             return Ok(set_mode(1, pos.try_into().unwrap()))
@@ -521,6 +567,7 @@ impl Compiler {
     match exp.clone() {
       Expr::String(s) => RC!(String, s),
       Expr::Number(n) => RC!(Number, n),
+      Expr::Integer(n) => RC!(Integer, n),
       Expr::Name(n) => {
         let v = self.get_var(n);
 
@@ -553,7 +600,7 @@ impl Compiler {
     } else if let Some(pos) = self.get_upval(name.clone()) {
       make_abc(Opcode::GetUpVal, reg.into(), pos.into(), 0)
     } else {
-      let pos = self.resolve_const(Value::String(name))?;
+      let pos = self.resolve_const(Value::String(name.into()))?;
       make_abx(Opcode::GetGlobal, reg.into(), pos)
     };
 
@@ -575,7 +622,7 @@ impl Compiler {
   }
 
   fn fix_jmp(&mut self, jmp_pos: usize, back: bool, jmp: usize) -> Result<(), String> {
-    self.closure.code[jmp_pos] = self.jmp(back, jmp + 1)?;
+    self.closure.code[jmp_pos + self.ncap] = self.jmp(back, jmp + 1)?;
     Ok(())
   }
 
@@ -591,7 +638,8 @@ impl Compiler {
     let mut pos: Option<u16> = None;
 
     for (i, val2) in self.closure.consts.iter().enumerate() {
-      if val == *val2 {
+      // `1` and `1.0` are equal but they aren't the same constant
+      if val == *val2 && discriminant(&val) == discriminant(val2) {
         pos = Some(i.try_into().unwrap()) // this shouldn't panic
       }
     }
@@ -624,11 +672,12 @@ impl Compiler {
       } else if var.name == name {
         let pos = self.ncap;
 
+        // `ni` doesn't count the captures so jumps recorded before this
+        // one are still right, `fix_jmp` skips them
         self.ncap += 1;
-        self.ni += 1;
 
         self.closure.code.insert(pos, make_abc(Opcode::GetUpVal, pos as u16, var.pos as u16, 1));
-        self.closure.lines.push(self.line);
+        self.closure.lines.insert(pos, self.line);
 
         var.captured = true;
         var.pos = pos as u8;
@@ -639,12 +688,6 @@ impl Compiler {
     None
   }
 
-  fn freeexp(&mut self) {
-    if self.freereg != 0 && self.nvars <= self.freereg {
-      self.freereg -= 1;
-    }
-  }
-
   fn reserve_regs(&mut self, reg: u8) -> Result<(), String> {
     let (_, err) = self.freereg.overflowing_add(reg);
 
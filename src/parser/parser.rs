@@ -1,5 +1,6 @@
 use crate::parser::ast::{ Stmt, Expr, UnOp, BinOp, Node };
-use crate::lexer::{ Lexer, Token };
+use crate::lexer::{ Lexer, Token, parse_number };
+use crate::common::Value;
 use std::process::exit;
 
 pub struct Parser {
@@ -51,8 +52,10 @@ impl Parser {
     macro_rules! stmt {
       ($i:expr) => {
         {
-          self.line = self.lex.line;
+          // nested statements change `self.line`
+          let line = self.lex.line;
           let res = $i;
+          self.line = line;
           if consume_semi { self.test_next(Token::Semi); }
           return Ok(res)
         }
@@ -244,8 +247,15 @@ impl Parser {
     match self.token {
       Token::True | Token::False => simple!(Bool, self.token == Token::True),
       Token::String => simple!(String, self.lex.buf.clone()),
-      Token::Number => simple!(Number, self.lex.buf.parse().unwrap()), // this shouldn't error
-      Token::Line => { self.next(); self.anon_func() },
+      Token::Template | Token::TemplateEnd => self.template(),
+      // the lexer already checked the literal so this shouldn't error
+      Token::Number => match parse_number(&self.lex.buf) {
+        Ok(Value::Integer(n)) => simple!(Integer, n),
+        Ok(Value::Number(n)) => simple!(Number, n),
+
+        _ => Err(self.error("malformed number", self.token))
+      },
+      Token::Line | Token::Or => self.anon_func(),
       Token::Nil => { self.next(); Ok(Expr::Nil) },
       Token::LeftBrace => { self.next(); self.table() }
       Token::LeftSquare => { self.next(); self.array() }
@@ -254,15 +264,30 @@ impl Parser {
     }
   }
 
-  fn sub_expr(&mut self, priority: u8) -> Result<Expr, String> {
-    let unop = self.get_unop();
-
-    let mut left = if let Some(op) = unop {
+  fn unary_expr(&mut self) -> Result<Expr, String> {
+    if let Some(op) = self.get_unop() {
       self.next();
-      Expr::Unary(op, self.simple_expr()?.boxed())
+      Ok(Expr::Unary(op, self.unary_expr()?.boxed()))
     } else {
-      self.simple_expr()?
-    };
+      self.pow_expr()
+    }
+  }
+
+  /// `**` is right associative and binds tighter than the unary operators on
+  /// its left, so `-2 ** 2` is `-(2 ** 2)` while `2 ** -1` is `2 ** (-1)`
+  fn pow_expr(&mut self) -> Result<Expr, String> {
+    let base = self.simple_expr()?;
+
+    if self.test_next(Token::DoubleStar) {
+      let exp = self.unary_expr()?;
+      Ok(Expr::Binary(base.boxed(), BinOp::Pow, exp.boxed()))
+    } else {
+      Ok(base)
+    }
+  }
+
+  fn sub_expr(&mut self, priority: u8) -> Result<Expr, String> {
+    let mut left = self.unary_expr()?;
 
     while let Some(op) = self.get_binop() {
       if op.priority() > priority {
@@ -285,8 +310,37 @@ impl Parser {
     self.sub_expr(0)
   }
 
+  fn template(&mut self) -> Result<Expr, String> {
+    let mut parts = Vec::new();
+
+    loop {
+      if !self.lex.buf.is_empty() {
+        parts.push(Expr::String(self.lex.buf.clone()));
+      }
+
+      if self.test_next(Token::TemplateEnd) { break }
+
+      self.next();
+      parts.push(self.expr()?);
+
+      // the lexer reads the rest of the string after the `}`
+      if !matches!(self.token, Token::Template | Token::TemplateEnd) {
+        return Err(self.error("expected '}'", self.token))
+      }
+    }
+
+    Ok(Expr::Template(parts))
+  }
+
   fn anon_func(&mut self) -> Result<Expr, String> {
-    let params = self.name_list(Token::Line)?;
+    // `||` is lexed as a single token
+    let params = if self.test_next(Token::Or) {
+      Vec::new()
+    } else {
+      self.check_next(Token::Line)?;
+      self.name_list(Token::Line)?
+    };
+
     let body = self.block()?;
 
     Ok(Expr::AnonFn(params, Box::new(body)))
@@ -302,7 +356,7 @@ impl Parser {
   fn dot_index(&mut self, exp: Expr) -> Result<Expr, String> {
     let idx = match self.token {
       Token::Number => {
-        self.expr()
+        self.simple_expr()
       }
 
       Token::Name => {
@@ -412,6 +466,7 @@ impl Parser {
     match self.token {
       Token::Bang => Some(UnOp::Not),
       Token::Dash => Some(UnOp::Neg),
+      Token::Tilde => Some(UnOp::BNot),
       _ => None
     }
   }
@@ -429,6 +484,12 @@ impl Parser {
       Token::Dash => Some(BinOp::Sub),
       Token::Star => Some(BinOp::Mul),
       Token::Slash => Some(BinOp::Div),
+      Token::DoubleSlash => Some(BinOp::IDiv),
+      Token::Amp => Some(BinOp::BAnd),
+      Token::Line => Some(BinOp::BOr),
+      Token::Caret => Some(BinOp::BXor),
+      Token::Shl => Some(BinOp::Shl),
+      Token::Shr => Some(BinOp::Shr),
       Token::Percent => Some(BinOp::Mod),
       Token::Equal => Some(BinOp::Assign),
 
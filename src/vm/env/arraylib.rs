@@ -0,0 +1,416 @@
+use std::cmp::Ordering;
+
+use crate::common::{ Value, Table, Array, Type };
+use crate::vm::{ VM, env::{ Env, aux::*, strlib::str_join }, RuntimeError };
+use crate::{ expect, expect_any, optional };
+
+pub fn load(env: &mut Env) {
+  let tbl = Table::new();
+
+  tbl_builtin(&tbl, "push", &array_push);
+  tbl_builtin(&tbl, "pop", &array_pop);
+  tbl_builtin(&tbl, "insert_at", &array_insert_at);
+  tbl_builtin(&tbl, "remove_at", &array_remove_at);
+  tbl_builtin(&tbl, "slice", &array_slice);
+  tbl_builtin(&tbl, "concat", &array_concat);
+  tbl_builtin(&tbl, "join", &str_join);
+  tbl_builtin(&tbl, "reverse", &array_reverse);
+  tbl_builtin(&tbl, "index_of", &array_index_of);
+  tbl_builtin(&tbl, "contains", &array_contains);
+  tbl_builtin(&tbl, "fill", &array_fill);
+  tbl_builtin(&tbl, "map", &array_map);
+  tbl_builtin(&tbl, "filter", &array_filter);
+  tbl_builtin(&tbl, "reduce", &array_reduce);
+  tbl_builtin(&tbl, "find", &array_find);
+  tbl_builtin(&tbl, "any", &array_any);
+  tbl_builtin(&tbl, "all", &array_all);
+  tbl_builtin(&tbl, "sort", &array_sort);
+  tbl_builtin(&tbl, "sort_by", &array_sort_by);
+  tbl_builtin(&tbl, "for_each", &array_for_each);
+
+  env.set_global(Value::String("array".into()), Value::Table(tbl))
+}
+
+/// checks an index argument against the length of the array, `end` allows
+/// the index right after the last element
+fn bound(idx: i64, len: usize, end: bool) -> Result<usize, RuntimeError> {
+  let idx = Array::validate_index(&Value::Integer(idx))?;
+
+  if idx < len || (end && idx == len) {
+    Ok(idx)
+  } else {
+    Err(RuntimeError::ArrayIdxBound)
+  }
+}
+
+/// appends every value after the array, returns the new length
+fn array_push(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let mut vals = get_all(vm);
+  let mut vec = array.vec.borrow_mut();
+
+  vec.append(&mut vals);
+
+  Ok(Value::Integer(vec.len() as i64))
+}
+
+/// removes the last value, nil if the array is empty
+fn array_pop(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let val = array.vec.borrow_mut().pop();
+
+  Ok(val.unwrap_or(Value::Nil))
+}
+
+/// inserts before `idx` shifting the rest up, `idx` can be the length
+fn array_insert_at(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let idx = expect!(Integer, vm)?;
+  let val = expect_any!(vm);
+
+  let mut vec = array.vec.borrow_mut();
+  let idx = bound(idx, vec.len(), true)?;
+
+  vec.insert(idx, val);
+
+  Ok(Value::Nil)
+}
+
+/// removes the value at `idx` shifting the rest down and returns it
+fn array_remove_at(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let idx = expect!(Integer, vm)?;
+
+  let mut vec = array.vec.borrow_mut();
+  let idx = bound(idx, vec.len(), false)?;
+
+  Ok(vec.remove(idx))
+}
+
+/// a new array with the values from `start` up to but not including `end`
+fn array_slice(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let vec = array.vec.borrow();
+
+  let start = bound(optional!(Integer, 0, vm), vec.len(), true)?;
+  let end = bound(optional!(Integer, vec.len() as i64, vm), vec.len(), true)?;
+
+  if start > end {
+    return Err(RuntimeError::ArrayIdxBound)
+  }
+
+  Ok(Value::Array(Array::new(vec[start .. end].to_vec())))
+}
+
+/// a new array with the values of every array argument in order
+fn array_concat(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let narg = vm.nci.top - vm.nci.base;
+  let mut vals = Vec::new();
+
+  for _ in 0 .. narg {
+    let array = expect!(Array, vm)?;
+    vals.extend(array.vec.borrow().iter().cloned());
+  }
+
+  Ok(Value::Array(Array::new(vals)))
+}
+
+/// reverses the array in place
+fn array_reverse(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  array.vec.borrow_mut().reverse();
+
+  Ok(Value::Nil)
+}
+
+/// the index of the first value equal to `val` starting at `init`, or nil
+fn array_index_of(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let val = expect_any!(vm);
+  let vec = array.vec.borrow();
+
+  let init = bound(optional!(Integer, 0, vm), vec.len(), true)?;
+  let idx = vec[init ..].iter().position(| v | *v == val);
+
+  Ok(idx.map_or(Value::Nil, | idx | Value::Integer((init + idx) as i64)))
+}
+
+fn array_contains(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let val = expect_any!(vm);
+
+  let found = array.vec.borrow().contains(&val);
+
+  Ok(Value::Bool(found))
+}
+
+/// sets every index from `start` up to but not including `end` to `val`
+fn array_fill(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let val = expect_any!(vm);
+  let mut vec = array.vec.borrow_mut();
+
+  let start = bound(optional!(Integer, 0, vm), vec.len(), true)?;
+  let end = bound(optional!(Integer, vec.len() as i64, vm), vec.len(), true)?;
+
+  if start > end {
+    return Err(RuntimeError::ArrayIdxBound)
+  }
+
+  for v in &mut vec[start .. end] {
+    *v = val.clone();
+  }
+
+  Ok(Value::Nil)
+}
+
+fn expect_func(vm: &mut VM, narg: usize) -> Result<Value, RuntimeError> {
+  let func = expect_any!(vm);
+
+  match func {
+    Value::Closure(..) | Value::NativeFunc(..) => Ok(func),
+    _ => Err(format!("bad argument #{} (expected function got {:?})", narg, Type::from(&func)).into())
+  }
+}
+
+/// calls `func` with every value and its index, the array isn't borrowed
+/// during the call so the callback can change it
+fn each<F>(vm: &mut VM, array: &Array, func: &Value, mut f: F) -> Result<(), RuntimeError>
+  where F: FnMut(Value, Value) -> bool {
+  let mut idx = 0;
+
+  loop {
+    let val = match array.vec.borrow().get(idx) {
+      Some(val) => val.clone(),
+      None => return Ok(())
+    };
+
+    let res = vm.call_value(func.clone(), vec![ val.clone(), Value::Integer(idx as i64) ])?;
+
+    if !f(val, res) {
+      return Ok(())
+    }
+
+    idx += 1;
+  }
+}
+
+/// a new array with the results of `func(val, idx)`
+fn array_map(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let func = expect_func(vm, 2)?;
+  let mut vals = Vec::with_capacity(array.len());
+
+  each(vm, &array, &func, | _, res | {
+    vals.push(res);
+    true
+  })?;
+
+  Ok(Value::Array(Array::new(vals)))
+}
+
+/// a new array with the values for which `func(val, idx)` is truthy
+fn array_filter(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let func = expect_func(vm, 2)?;
+  let mut vals = Vec::new();
+
+  each(vm, &array, &func, | val, res | {
+    if res.truthy() { vals.push(val) }
+    true
+  })?;
+
+  Ok(Value::Array(Array::new(vals)))
+}
+
+/// folds the array with `func(acc, val, idx)`, starting with `init` or the
+/// first value if there is none
+fn array_reduce(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let func = expect_func(vm, 2)?;
+
+  let (mut acc, mut idx) = match try_get(vm) {
+    Some(init) => (init, 0),
+    None => match array.vec.borrow().first() {
+      Some(first) => (first.clone(), 1),
+      None => return Err("bad argument #3 (empty array and no initial value)".into())
+    }
+  };
+
+  loop {
+    let val = match array.vec.borrow().get(idx) {
+      Some(val) => val.clone(),
+      None => return Ok(acc)
+    };
+
+    acc = vm.call_value(func.clone(), vec![ acc, val, Value::Integer(idx as i64) ])?;
+    idx += 1;
+  }
+}
+
+/// the first value for which `func(val, idx)` is truthy, or nil
+fn array_find(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let func = expect_func(vm, 2)?;
+  let mut found = Value::Nil;
+
+  each(vm, &array, &func, | val, res | {
+    if res.truthy() { found = val }
+    !res.truthy()
+  })?;
+
+  Ok(found)
+}
+
+fn array_any(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let func = expect_func(vm, 2)?;
+  let mut any = false;
+
+  each(vm, &array, &func, | _, res | {
+    any = res.truthy();
+    !any
+  })?;
+
+  Ok(Value::Bool(any))
+}
+
+/// true for an empty array
+fn array_all(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let func = expect_func(vm, 2)?;
+  let mut all = true;
+
+  each(vm, &array, &func, | _, res | {
+    all = res.truthy();
+    all
+  })?;
+
+  Ok(Value::Bool(all))
+}
+
+/// sorts the array in place by the keys `func(val, idx)` gives, values with
+/// the same key keep their order
+fn array_sort_by(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let func = expect_func(vm, 2)?;
+  let mut keyed = Vec::with_capacity(array.len());
+
+  each(vm, &array, &func, | val, key | {
+    keyed.push((key, val));
+    true
+  })?;
+
+  let keys = keyed.iter().map(| (key, _) | key);
+  check_sortable(keys)?;
+
+  merge_sort(&mut keyed, &mut | (a, _), (b, _) | Ok(natural_lt(a, b)))?;
+
+  *array.vec.borrow_mut() = keyed.into_iter().map(| (_, val) | val).collect();
+
+  Ok(Value::Nil)
+}
+
+fn array_for_each(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let func = expect_func(vm, 2)?;
+
+  each(vm, &array, &func, | _, _ | true)?;
+
+  Ok(Value::Nil)
+}
+
+/// sorts the array in place, by `cmp(a, b)` if given which should be truthy
+/// when `a` goes before `b`, or else by the natural order of numbers or of
+/// strings. The sort is stable.
+fn array_sort(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let cmp = try_get(vm).filter(| cmp | *cmp != Value::Nil);
+
+  // the array stays usable while the comparator runs
+  let mut vals = array.vec.borrow().clone();
+
+  if let Some(cmp) = cmp {
+    if !matches!(cmp, Value::Closure(..) | Value::NativeFunc(..)) {
+      return Err(format!("bad argument #2 (expected function got {:?})", Type::from(&cmp)).into())
+    }
+
+    merge_sort(&mut vals, &mut | a, b | {
+      let res = vm.call_value(cmp.clone(), vec![ a.clone(), b.clone() ])?;
+      Ok(res.truthy())
+    })?;
+  } else {
+    check_sortable(vals.iter())?;
+    merge_sort(&mut vals, &mut | a, b | Ok(natural_lt(a, b)))?;
+  }
+
+  *array.vec.borrow_mut() = vals;
+
+  Ok(Value::Nil)
+}
+
+/// a top down merge sort, unlike `slice::sort_by` the comparison can fail
+/// since it may call into the script
+fn merge_sort<T, F>(vals: &mut Vec<T>, less: &mut F) -> Result<(), RuntimeError>
+  where F: FnMut(&T, &T) -> Result<bool, RuntimeError> {
+  let len = vals.len();
+
+  if len < 2 {
+    return Ok(())
+  }
+
+  let mut right = vals.split_off(len / 2);
+
+  merge_sort(vals, less)?;
+  merge_sort(&mut right, less)?;
+
+  let mut left = std::mem::take(vals).into_iter().peekable();
+  let mut right = right.into_iter().peekable();
+
+  vals.reserve(len);
+
+  loop {
+    // equal values are taken from the left so the sort is stable
+    let val = match (left.peek(), right.peek()) {
+      (Some(l), Some(r)) => if less(r, l)? { right.next() } else { left.next() },
+      (Some(_), None) => left.next(),
+      (None, Some(_)) => right.next(),
+      (None, None) => return Ok(())
+    };
+
+    vals.push(val.unwrap());
+  }
+}
+
+/// only numbers with numbers and strings with strings have a natural order
+fn check_sortable<'a, I>(mut vals: I) -> Result<(), RuntimeError>
+  where I: Iterator<Item = &'a Value> {
+  let first = match vals.next() {
+    Some(first) => first,
+    None => return Ok(())
+  };
+
+  let kind = |val: &Value| match val {
+    Value::Integer(..) | Value::Number(..) => Some(0),
+    Value::String(..) => Some(1),
+    _ => None
+  };
+
+  if kind(first).is_none() {
+    return Err(RuntimeError::TypeError("compare".into(), Type::from(first), None))
+  }
+
+  for val in vals {
+    if kind(val) != kind(first) {
+      return Err(RuntimeError::TypeError("compare".into(), Type::from(first), Some(Type::from(val))))
+    }
+  }
+
+  Ok(())
+}
+
+/// `check_sortable` makes sure the values can be compared, NaN is never
+/// lower than anything
+fn natural_lt(a: &Value, b: &Value) -> bool {
+  a.partial_cmp(b) == Some(Ordering::Less)
+}
@@ -6,12 +6,21 @@ use crate::vm::{ VM, RuntimeError };
 pub fn tbl_builtin(tbl: &Table, name: &str, func: &'static BuiltIn) {
   let rf = RustFunc {
     name: name.clone().into(),
-    func: func
+    func: Rc::new(func)
   };
 
   tbl.insert(Value::String(name.into()), Value::NativeFunc(Rc::new(rf))).unwrap();
 }
 
+/// makes a native function out of a closure, used for iterators
+pub fn native_closure<F>(name: &str, func: F) -> Value
+  where F: Fn(&mut VM) -> Result<Value, RuntimeError> + 'static {
+  Value::NativeFunc(Rc::new(RustFunc {
+    name: name.into(),
+    func: Rc::new(func)
+  }))
+}
+
 pub fn get(vm: &mut VM) -> Result<Value, RuntimeError> {
   vm.nci.base += 1; // so this never gets read again
 
@@ -33,6 +42,11 @@ pub fn try_get(vm: &mut VM) -> Option<Value> {
   }
 }
 
+/// `bad argument #n (msg)` for the argument that was read last
+pub fn bad_argument(vm: &VM, msg: String) -> RuntimeError {
+  format!("bad argument #{} ({})", vm.nci.base - vm.nci.start, msg).into()
+}
+
 pub fn get_all(vm: &mut VM) -> Vec<Value> {
   let mut vals = Vec::new();
 
@@ -52,6 +66,28 @@ macro_rules! get_all {
 
 #[macro_export]
 macro_rules! expect {
+  // integers are numbers too
+  (Number, $vm:ident) => {{
+    let val = crate::vm::env::aux::get($vm)?;
+
+    if let Some(x) = val.to_float() {
+      Ok(x)
+    } else {
+      Err(format!("expected number got {:?}", crate::common::Type::from(&val)))
+    }}
+  };
+
+  // accepts floats with an exact integer value
+  (Integer, $vm:ident) => {{
+    let val = crate::vm::env::aux::get($vm)?;
+
+    match (val.to_integer(), val.to_float()) {
+      (Some(x), _) => Ok(x),
+      (None, Some(_)) => Err("number has no integer representation".to_string()),
+      _ => Err(format!("expected number got {:?}", crate::common::Type::from(&val)))
+    }}
+  };
+
   ($id:ident, $vm:ident) => {{
     let val = crate::vm::env::aux::get($vm)?;
 
@@ -70,17 +106,52 @@ macro_rules! expect_any {
   };
 }
 
+/// Reads an argument that can be left out or nil to get `$or`, anything
+/// else has to have the right type.
 #[macro_export]
 macro_rules! optional {
-  ($id:ident, $or:expr, $vm:ident) => {{
-    let val = crate::vm::env::aux::get($vm)?;
+  (Number, $or:expr, $vm:ident) => {{
+    match crate::vm::env::aux::try_get($vm) {
+      None | Some(Value::Nil) => $or,
+
+      Some(val) => match val.to_float() {
+        Some(x) => x,
+        None => {
+          let err = format!("expected number got {:?}", crate::common::Type::from(&val));
+          return Err(crate::vm::env::aux::bad_argument($vm, err))
+        }
+      }
+    }
+  }};
+
+  (Integer, $or:expr, $vm:ident) => {{
+    match crate::vm::env::aux::try_get($vm) {
+      None | Some(Value::Nil) => $or,
+
+      Some(val) => match (val.to_integer(), val.to_float()) {
+        (Some(x), _) => x,
+        (None, Some(_)) => {
+          return Err(crate::vm::env::aux::bad_argument($vm, "number has no integer representation".into()))
+        }
+        _ => {
+          let err = format!("expected number got {:?}", crate::common::Type::from(&val));
+          return Err(crate::vm::env::aux::bad_argument($vm, err))
+        }
+      }
+    }
+  }};
 
-    if let Value::$id(x) = val {
-      x.clone()
-    } else {
-      $or
-    }}
-  };
+  ($id:ident, $or:expr, $vm:ident) => {{
+    match crate::vm::env::aux::try_get($vm) {
+      None | Some(Value::Nil) => $or,
+      Some(Value::$id(x)) => x.clone(),
+
+      Some(val) => {
+        let err = format!("expected {} got {:?}", stringify!($id).to_lowercase(), crate::common::Type::from(&val));
+        return Err(crate::vm::env::aux::bad_argument($vm, err))
+      }
+    }
+  }};
 }
 
 #[macro_export]
@@ -0,0 +1,278 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::common::{ Value, Type, inspect, InspectOptions };
+use crate::vm::{ VM, env::aux::*, RuntimeError };
+use crate::expect;
+
+/// a parsed `%[flags][width][.precision]conversion`
+#[derive(Default)]
+struct Spec {
+  left: bool,
+  zero: bool,
+  plus: bool,
+  space: bool,
+  alt: bool,
+  width: usize,
+  precision: Option<usize>,
+  conv: char
+}
+
+/// `string.format(fmt, ...)` with the C conversions `%d %i %f %e %g %s %q %x
+/// %o %c %%`, the flags `- 0 + space #`, a width and a precision
+pub fn str_format(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let fmt = expect!(String, vm)?;
+  let mut out = String::with_capacity(fmt.len());
+  let mut chars = fmt.chars().peekable();
+  let mut narg = 1;
+
+  while let Some(c) = chars.next() {
+    if c != '%' {
+      out.push(c);
+      continue
+    }
+
+    let spec = parse_spec(&mut chars).map_err(| e | format!("bad argument #1 ({})", e))?;
+
+    if spec.conv == '%' {
+      out.push('%');
+      continue
+    }
+
+    narg += 1;
+
+    let val = try_get(vm).ok_or_else(|| format!("bad argument #{} (no value)", narg))?;
+    let str = format_value(&spec, &val).map_err(| e | format!("bad argument #{} ({})", narg, e))?;
+
+    out.push_str(&str);
+  }
+
+  Ok(Value::String(out.into()))
+}
+
+fn parse_spec(chars: &mut Peekable<Chars>) -> Result<Spec, String> {
+  let mut spec = Spec::default();
+
+  while let Some(c) = chars.peek() {
+    match c {
+      '-' => spec.left = true,
+      '0' => spec.zero = true,
+      '+' => spec.plus = true,
+      ' ' => spec.space = true,
+      '#' => spec.alt = true,
+
+      _ => break
+    }
+
+    chars.next();
+  }
+
+  spec.width = read_int(chars)?;
+
+  if chars.peek() == Some(&'.') {
+    chars.next();
+    spec.precision = Some(read_int(chars)?);
+  }
+
+  spec.conv = match chars.next() {
+    Some(c @ ('d' | 'i' | 'x' | 'X' | 'o' | 'c' | 'f' | 'F' | 'e' | 'E' | 'g' | 'G' | 's' | 'q' | '%')) => c,
+    Some(c) => return Err(format!("invalid conversion '%{}' to format", c)),
+    None => return Err("invalid conversion '%' to format".into())
+  };
+
+  Ok(spec)
+}
+
+/// widths and precisions have at most two digits like in Lua
+fn read_int(chars: &mut Peekable<Chars>) -> Result<usize, String> {
+  let mut n = 0;
+  let mut len = 0;
+
+  while let Some(digit) = chars.peek().and_then(| c | c.to_digit(10)) {
+    n = n * 10 + digit as usize;
+    len += 1;
+    chars.next();
+  }
+
+  if len > 2 {
+    Err("invalid conversion (width or precision too long)".into())
+  } else {
+    Ok(n)
+  }
+}
+
+fn format_value(spec: &Spec, val: &Value) -> Result<String, String> {
+  match spec.conv {
+    'd' | 'i' => {
+      let n = int_arg(val)?;
+      let mut digits = n.unsigned_abs().to_string();
+
+      if let Some(prec) = spec.precision {
+        digits = format!("{:0>1$}", digits, prec);
+      }
+
+      Ok(pad_number(spec, sign(spec, n < 0), "", &digits, spec.precision.is_none()))
+    }
+
+    'x' | 'X' | 'o' => {
+      // negative numbers are shown in two's complement
+      let n = int_arg(val)? as u64;
+
+      let (digits, prefix) = match spec.conv {
+        'x' => (format!("{:x}", n), "0x"),
+        'X' => (format!("{:X}", n), "0X"),
+        _ => (format!("{:o}", n), "0")
+      };
+
+      let prefix = if spec.alt && n != 0 { prefix } else { "" };
+
+      Ok(pad_number(spec, "", prefix, &digits, true))
+    }
+
+    'c' => {
+      let n = int_arg(val)?;
+      let c = std::char::from_u32(n as u32).filter(| _ | (0 ..= u32::MAX as i64).contains(&n));
+
+      Ok(pad(spec, c.ok_or("value out of range")?.to_string()))
+    }
+
+    'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+      let n = val.to_float().ok_or_else(|| expected_number(val))?;
+      let mut digits = format_float(spec, n.abs());
+
+      if spec.conv.is_ascii_uppercase() {
+        digits = digits.to_uppercase();
+      }
+
+      Ok(pad_number(spec, sign(spec, n.is_sign_negative() && !n.is_nan()), "", &digits, n.is_finite()))
+    }
+
+    's' => {
+      let mut str = val.to_string();
+
+      if let Some(prec) = spec.precision {
+        str = str.chars().take(prec).collect();
+      }
+
+      Ok(pad(spec, str))
+    }
+
+    'q' => Ok(pad(spec, quote(val)?)),
+
+    _ => unreachable!()
+  }
+}
+
+fn int_arg(val: &Value) -> Result<i64, String> {
+  match (val.to_integer(), val.to_float()) {
+    (Some(n), _) => Ok(n),
+    (None, Some(_)) => Err("number has no integer representation".into()),
+    _ => Err(expected_number(val))
+  }
+}
+
+fn expected_number(val: &Value) -> String {
+  format!("expected number got {:?}", Type::from(val))
+}
+
+fn sign(spec: &Spec, neg: bool) -> &'static str {
+  if neg {
+    "-"
+  } else if spec.plus {
+    "+"
+  } else if spec.space {
+    " "
+  } else {
+    ""
+  }
+}
+
+/// pads to the width, with zeros between the sign and the digits if asked
+fn pad_number(spec: &Spec, sign: &str, prefix: &str, digits: &str, can_zero: bool) -> String {
+  let len = sign.len() + prefix.len() + digits.chars().count();
+
+  if spec.zero && can_zero && !spec.left && len < spec.width {
+    format!("{}{}{}{}", sign, prefix, "0".repeat(spec.width - len), digits)
+  } else {
+    pad(spec, format!("{}{}{}", sign, prefix, digits))
+  }
+}
+
+fn pad(spec: &Spec, str: String) -> String {
+  let len = str.chars().count();
+
+  if len >= spec.width {
+    str
+  } else if spec.left {
+    str + &" ".repeat(spec.width - len)
+  } else {
+    " ".repeat(spec.width - len) + &str
+  }
+}
+
+/// formats a positive float, the sign is handled by `pad_number`
+fn format_float(spec: &Spec, n: f64) -> String {
+  if n.is_nan() { return "nan".into() }
+  if n.is_infinite() { return "inf".into() }
+
+  let prec = spec.precision.unwrap_or(6);
+
+  match spec.conv.to_ascii_lowercase() {
+    'f' => {
+      let str = format!("{:.*}", prec, n);
+      if spec.alt && prec == 0 { str + "." } else { str }
+    }
+
+    'e' => format_exp(n, prec, spec.alt),
+
+    _ => {
+      let prec = prec.max(1);
+
+      // the exponent after rounding decides between `%e` and `%f`
+      let exp = format!("{:.*e}", prec - 1, n);
+      let exp: i32 = exp[exp.find('e').unwrap() + 1 ..].parse().unwrap();
+
+      let str = if exp < -4 || exp >= prec as i32 {
+        format_exp(n, prec - 1, spec.alt)
+      } else {
+        format!("{:.*}", (prec as i32 - 1 - exp) as usize, n)
+      };
+
+      if spec.alt { str } else { strip_zeros(&str) }
+    }
+  }
+}
+
+/// `%e` with a sign and at least two digits in the exponent like C
+fn format_exp(n: f64, prec: usize, alt: bool) -> String {
+  let str = format!("{:.*e}", prec, n);
+  let (mantissa, exp) = str.split_at(str.find('e').unwrap());
+  let exp: i32 = exp[1 ..].parse().unwrap();
+
+  let dot = if alt && prec == 0 { "." } else { "" };
+  let sign = if exp < 0 { '-' } else { '+' };
+
+  format!("{}{}e{}{:02}", mantissa, dot, sign, exp.abs())
+}
+
+/// removes the trailing zeros of the fractional part for `%g`
+fn strip_zeros(str: &str) -> String {
+  let (mantissa, exp) = str.split_at(str.find('e').unwrap_or(str.len()));
+
+  if mantissa.contains('.') {
+    format!("{}{}", mantissa.trim_end_matches('0').trim_end_matches('.'), exp)
+  } else {
+    str.into()
+  }
+}
+
+/// a literal that reads back as the same value
+fn quote(val: &Value) -> Result<String, String> {
+  match val {
+    Value::String(..) | Value::Number(..) | Value::Integer(..) | Value::Bool(..) | Value::Nil => {
+      Ok(inspect(val, InspectOptions::default()))
+    }
+
+    _ => Err("value has no literal form".into())
+  }
+}
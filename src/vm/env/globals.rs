@@ -2,11 +2,15 @@ use std::time::{ SystemTime, UNIX_EPOCH };
 use std::io::Write;
 use std::io;
 
-use crate::common::{ Value, Type };
+use crate::common::{ Value, Type, Table, inspect, InspectOptions };
+use crate::lexer::parse_number;
 use crate::vm::{ VM, env::{ Env, aux::try_get }, RuntimeError };
-use crate::{ expect, expect_any, get_all, optional };
+use crate::{ expect, expect_any, get_all, optional, arg_check };
 
 pub fn load(env: &mut Env) {
+  env.builtin("collectgarbage", &collectgarbage);
+  env.builtin("getmetatable", &getmetatable);
+  env.builtin("setmetatable", &setmetatable);
   env.builtin("tonumber", &tonumber);
   env.builtin("argcheck", &argcheck);
   env.builtin("print", &print);
@@ -17,10 +21,11 @@ pub fn load(env: &mut Env) {
   env.builtin("next", &next);
   env.builtin("type", &gettype);
   env.builtin("len", &len);
+  env.builtin("inspect", &inspect_value);
 }
 
 fn gettype(vm: &mut VM) -> Result<Value, RuntimeError> {
-  Ok(Value::String(Type::from(&expect_any!(vm)).to_string()))
+  Ok(Value::String(Type::from(&expect_any!(vm)).to_string().into()))
 }
 
 fn argcheck(vm: &mut VM) -> Result<Value, RuntimeError> {
@@ -51,7 +56,7 @@ fn argcheck(vm: &mut VM) -> Result<Value, RuntimeError> {
     let arg = args.remove(0);
     let arg_type = Type::from(&arg).to_string();
 
-    if arg_type != expected_type {
+    if arg_type != expected_type.as_str() {
       return Err(format!("bad argument #{}: expected {} got {}", n, expected_type, arg_type).into())
     }
 
@@ -61,38 +66,95 @@ fn argcheck(vm: &mut VM) -> Result<Value, RuntimeError> {
   Ok(Value::Nil)
 }
 
+fn collectgarbage(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let opt = optional!(String, "collect".into(), vm);
+
+  match opt.as_str() {
+    "collect" => Ok(Value::Integer(vm.collect_garbage()? as i64)),
+    "count" => Ok(Value::Number(vm.heap_stats().bytes as f64 / 1024.0)),
+
+    _ => Err(format!("bad argument #1 (invalid option '{}')", opt).into())
+  }
+}
+
+/// a metatable with a `__metatable` field can't be changed
+fn setmetatable(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let tbl = expect!(Table, vm)?;
+  let meta = expect_any!(vm);
+
+  if tbl.get_meta("__metatable") != Value::Nil {
+    return Err("cannot change a protected metatable".into())
+  }
+
+  match meta {
+    Value::Table(meta) => tbl.set_metatable(Some(meta)),
+    Value::Nil => tbl.set_metatable(None),
+
+    _ => return Err(format!("bad argument #2 (expected table or nil got {:?})", Type::from(&meta)).into())
+  }
+
+  if tbl.get_meta("__gc") != Value::Nil {
+    vm.heap.set_finalizer(&tbl);
+  }
+
+  Ok(Value::Table(tbl))
+}
+
+/// gives the `__metatable` field instead of the metatable if there is one
+fn getmetatable(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let tbl = expect!(Table, vm)?;
+
+  match tbl.get_meta("__metatable") {
+    Value::Nil => Ok(tbl.metatable().map(Value::Table).unwrap_or(Value::Nil)),
+    protected => Ok(protected)
+  }
+}
+
 fn tonumber(vm: &mut VM) -> Result<Value, RuntimeError> {
   let val = expect_any!(vm);
-  let base = (optional!(Number, 10.0, vm) as u32).clamp(2, 32);
+  let base = optional!(Integer, 10, vm).clamp(2, 36) as u32;
 
   match val {
     Value::String(s) => {
-      let n = u64::from_str_radix(s.as_str(), base);
+      let s = s.trim();
 
-      Ok(if let Ok(n) = n {
-        Value::Number(n as f64)
-      } else {
-        Value::Nil
+      if base != 10 {
+        return Ok(i64::from_str_radix(s, base).map(Value::Integer).unwrap_or(Value::Nil))
+      }
+
+      // same literals as in the source, with an optional sign
+      let (neg, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s.strip_prefix('+').unwrap_or(s))
+      };
+
+      Ok(match parse_number(digits) {
+        Ok(Value::Integer(n)) if neg => Value::Integer(n.wrapping_neg()),
+        Ok(Value::Number(n)) if neg => Value::Number(-n),
+        Ok(n) => n,
+        Err(..) => Value::Nil
       })
     }
 
+    Value::Number(..) | Value::Integer(..) => Ok(val),
+
     _ => Ok(Value::Nil)
   }
 }
 
 fn next(vm: &mut VM) -> Result<Value, RuntimeError> {
   let val = expect_any!(vm);
-  let idx = optional!(Number, 0.0, vm) as usize;
+  let idx = optional!(Integer, 0, vm);
 
   match val {
     Value::Table(t) => {
       let tbl = t.tbl.borrow();
-      let (_, val) = tbl.iter().nth(idx).unwrap_or((&Value::Nil, &Value::Nil));
+      let (_, val) = tbl.iter().nth(idx as usize).unwrap_or((&Value::Nil, &Value::Nil));
       Ok(val.clone())
     }
 
     Value::Array(a) => {
-      let v = a.get(&Value::Number(idx as f64))?;
+      let v = a.get(&Value::Integer(idx))?;
 
       Ok(v.clone())
     }
@@ -107,13 +169,13 @@ fn len(vm: &mut VM) -> Result<Value, RuntimeError> {
   let n = match val.clone() {
     Value::Array(a) => Some(a.len()),
     Value::Table(t) => Some(t.len()),
-    Value::String(s) => Some(s.len()),
+    Value::String(s) => Some(s.char_len()),
 
     _ => None
   };
 
   if let Some(n) = n {
-    Ok(Value::Number(n as f64))
+    Ok(Value::Integer(n as i64))
   } else {
     Err(RuntimeError::TypeError("get len".into(), Type::from(&val), None))
   }
@@ -148,7 +210,7 @@ fn clock(_vm: &mut VM) -> Result<Value, RuntimeError> {
 
 fn error(vm: &mut VM) -> Result<Value, RuntimeError> {
   let s = expect!(String, vm)?;
-  Err(s.into())
+  Err(s.as_str().into())
 }
 
 fn read(_vm: &mut VM) -> Result<Value, RuntimeError> {
@@ -164,6 +226,32 @@ fn read(_vm: &mut VM) -> Result<Value, RuntimeError> {
   if res.is_err() {
     Err("unable to read from stdin".into())
   } else {
-    Ok(Value::String(str.trim().to_string()))
+    Ok(Value::String(str.trim().into()))
   }
-}
\ No newline at end of file
+}
+
+/// `inspect(value, {depth, indent})` shows a value as a Moon literal
+fn inspect_value(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let val = expect_any!(vm);
+  let opts = optional!(Table, Table::new(), vm);
+
+  let get_opt = | name: &str | -> Result<Option<usize>, RuntimeError> {
+    match opts.get(&Value::String(name.into()))? {
+      Value::Nil => Ok(None),
+
+      n => {
+        let n = n.to_integer().filter(| n | *n >= 0);
+        arg_check!(n.is_some(), 2, format!("{} must be a non-negative integer", name))?;
+
+        Ok(n.map(| n | n as usize))
+      }
+    }
+  };
+
+  let opts = InspectOptions {
+    depth: get_opt("depth")?,
+    indent: get_opt("indent")?
+  };
+
+  Ok(Value::String(inspect(&val, opts).into()))
+}
@@ -0,0 +1,288 @@
+use std::io::{ self, BufRead, BufReader, Read, Seek, SeekFrom, Write };
+use std::fs::{ File, OpenOptions };
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::common::{ Value, Table, Type, inspect, InspectOptions };
+use crate::vm::{ VM, env::{ Env, aux::* }, RuntimeError };
+use crate::{ expect, optional, get_all };
+
+/// What a handle reads from and writes to. Files are buffered for reading
+/// and the buffer is dropped before writing or seeking so the position stays
+/// right.
+enum Stream {
+  File(BufReader<File>),
+  Stdin,
+  Stdout,
+  Stderr,
+  Closed
+}
+
+type Shared = Rc<RefCell<Stream>>;
+
+thread_local! {
+  /// why the last open or read failed, cleared by the next one that doesn't
+  static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub fn load(env: &mut Env) {
+  let tbl = Table::new();
+
+  tbl_builtin(&tbl, "open", &io_open);
+  tbl_builtin(&tbl, "lasterror", &io_lasterror);
+
+  for (name, stream) in [("stdin", Stream::Stdin), ("stdout", Stream::Stdout), ("stderr", Stream::Stderr)] {
+    tbl.insert(Value::String(name.into()), handle(stream)).unwrap();
+  }
+
+  env.set_global(Value::String("io".into()), Value::Table(tbl))
+}
+
+/// `io.open(path, mode)` with the modes of `fopen`: `r`, `w`, `a`, `r+`,
+/// `w+` and `a+`, a `b` is allowed and ignored. Gives nil if the file can't
+/// be opened.
+fn io_open(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let path = expect!(String, vm)?;
+  let mode = optional!(String, "r".into(), vm);
+
+  let mut opts = OpenOptions::new();
+
+  match mode.replace('b', "").as_str() {
+    "r" => opts.read(true),
+    "w" => opts.write(true).create(true).truncate(true),
+    "a" => opts.append(true).create(true),
+    "r+" => opts.read(true).write(true),
+    "w+" => opts.read(true).write(true).create(true).truncate(true),
+    "a+" => opts.read(true).append(true).create(true),
+
+    _ => return Err(format!("bad argument #2 (invalid mode '{}')", mode).into())
+  };
+
+  let res = opts.open(&*path)
+    .map(| file | handle(Stream::File(BufReader::new(file))))
+    .map_err(| e | format!("{}: {}", path, io_error(e)));
+
+  Ok(or_nil(res))
+}
+
+/// the message of the last open or read that gave nil because it failed, or
+/// nil if it didn't
+fn io_lasterror(_vm: &mut VM) -> Result<Value, RuntimeError> {
+  let err = LAST_ERROR.with(| last | last.borrow().clone());
+
+  Ok(err.map(| e | Value::String(e.into())).unwrap_or(Value::Nil))
+}
+
+/// nil instead of an error, the message is kept for `io.lasterror()`
+fn or_nil(res: Result<Value, String>) -> Value {
+  let (val, err) = match res {
+    Ok(val) => (val, None),
+    Err(e) => (Value::Nil, Some(e))
+  };
+
+  LAST_ERROR.with(| last | *last.borrow_mut() = err);
+  val
+}
+
+/// the message without the `(os error n)` rust adds
+pub fn io_error(e: io::Error) -> String {
+  let msg = e.to_string();
+
+  match msg.find(" (os error") {
+    Some(i) => msg[.. i].into(),
+    None => msg
+  }
+}
+
+/// a table with the methods of a handle, they keep the stream so they're
+/// called with `f.read("line")`
+fn handle(stream: Stream) -> Value {
+  let stream = Rc::new(RefCell::new(stream));
+  let tbl = Table::new();
+
+  let method = | name: &str, func: fn(&mut VM, &Shared) -> Result<Value, RuntimeError> | {
+    let stream = stream.clone();
+    let func = native_closure(name, move | vm | func(vm, &stream));
+
+    tbl.insert(Value::String(name.into()), func).unwrap();
+  };
+
+  method("read", file_read);
+  method("lines", file_lines);
+  method("write", file_write);
+  method("seek", file_seek);
+  method("flush", file_flush);
+  method("close", file_close);
+
+  Value::Table(tbl)
+}
+
+/// `read("line")` reads a line without the newline, `read("all")` the rest
+/// and `read(n)` up to `n` bytes. Gives nil at the end, except for `all`,
+/// and if reading fails.
+fn file_read(vm: &mut VM, stream: &Shared) -> Result<Value, RuntimeError> {
+  let fmt = try_get(vm).unwrap_or_else(|| Value::String("line".into()));
+  let mut stream = stream.borrow_mut();
+
+  match &fmt {
+    Value::String(s) if &**s == "line" => read_line(&mut stream),
+    Value::String(s) if &**s == "all" => with_reader(&mut stream, | r | {
+      let mut str = String::new();
+      r.read_to_string(&mut str)?;
+
+      Ok(Value::String(str.into()))
+    }),
+
+    Value::Integer(..) | Value::Number(..) => {
+      let n = fmt.to_integer().filter(| n | *n >= 0);
+      let n = n.ok_or("bad argument #1 (expected a non-negative integer)")?;
+
+      read_bytes(&mut stream, n as u64)
+    }
+
+    _ => Err(format!("bad argument #1 (invalid format {})", inspect(&fmt, InspectOptions::default())).into())
+  }
+}
+
+fn read_line(stream: &mut Stream) -> Result<Value, RuntimeError> {
+  with_reader(stream, | r | {
+    let mut str = String::new();
+
+    if r.read_line(&mut str)? == 0 {
+      return Ok(Value::Nil)
+    }
+
+    if str.ends_with('\n') { str.pop(); }
+    if str.ends_with('\r') { str.pop(); }
+
+    Ok(Value::String(str.into()))
+  })
+}
+
+fn read_bytes(stream: &mut Stream, n: u64) -> Result<Value, RuntimeError> {
+  with_reader(stream, | r | {
+    let mut bytes = Vec::new();
+    r.take(n).read_to_end(&mut bytes)?;
+
+    if bytes.is_empty() && n > 0 {
+      return Ok(Value::Nil)
+    }
+
+    match String::from_utf8(bytes) {
+      Ok(str) => Ok(Value::String(str.into())),
+      Err(..) => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid UTF-8 sequence"))
+    }
+  })
+}
+
+fn with_reader<F>(stream: &mut Stream, f: F) -> Result<Value, RuntimeError>
+  where F: FnOnce(&mut dyn BufRead) -> io::Result<Value> {
+  let res = match stream {
+    Stream::File(file) => f(file),
+    Stream::Stdin => {
+      io::stdout().flush().ok();
+      f(&mut io::stdin().lock())
+    }
+
+    Stream::Stdout | Stream::Stderr => return Err("file is not readable".into()),
+    Stream::Closed => return Err("attempt to use a closed file".into())
+  };
+
+  Ok(or_nil(res.map_err(io_error)))
+}
+
+/// `lines()` returns a function that reads the next line every time it's
+/// called, and nil at the end
+fn file_lines(_: &mut VM, stream: &Shared) -> Result<Value, RuntimeError> {
+  let stream = stream.clone();
+
+  Ok(native_closure("lines iterator", move | _ | read_line(&mut stream.borrow_mut())))
+}
+
+/// writes strings and numbers without separators
+fn file_write(vm: &mut VM, stream: &Shared) -> Result<Value, RuntimeError> {
+  let vals = get_all!(vm);
+  let mut str = String::new();
+
+  for (i, val) in vals.iter().enumerate() {
+    match val {
+      Value::String(..) | Value::Integer(..) | Value::Number(..) => str.push_str(&val.to_string()),
+      _ => return Err(format!("bad argument #{} (expected string got {:?})", i + 1, Type::from(val)).into())
+    }
+  }
+
+  let res = match &mut *stream.borrow_mut() {
+    Stream::File(file) => {
+      // drops what was read ahead so the write goes where the reads stopped
+      file.stream_position()
+        .and_then(| pos | file.seek(SeekFrom::Start(pos)))
+        .and_then(| _ | file.get_mut().write_all(str.as_bytes()))
+    }
+
+    Stream::Stdout => io::stdout().write_all(str.as_bytes()),
+    Stream::Stderr => io::stderr().write_all(str.as_bytes()),
+    Stream::Stdin => return Err("file is not writable".into()),
+    Stream::Closed => return Err("attempt to use a closed file".into())
+  };
+
+  res.map_err(io_error)?;
+
+  Ok(Value::Nil)
+}
+
+/// `seek(whence, offset)` where whence is `set`, `cur` or `end`, returns
+/// the new position from the start of the file
+fn file_seek(vm: &mut VM, stream: &Shared) -> Result<Value, RuntimeError> {
+  let whence = optional!(String, "cur".into(), vm);
+  let offset = optional!(Integer, 0, vm);
+
+  let pos = match whence.as_str() {
+    "set" if offset >= 0 => SeekFrom::Start(offset as u64),
+    "set" => return Err("bad argument #2 (position must not be negative)".into()),
+    "cur" => SeekFrom::Current(offset),
+    "end" => SeekFrom::End(offset),
+
+    _ => return Err(format!("bad argument #1 (invalid option '{}')", whence).into())
+  };
+
+  match &mut *stream.borrow_mut() {
+    Stream::File(file) => {
+      let pos = file.seek(pos).map_err(io_error)?;
+      Ok(Value::Integer(pos as i64))
+    }
+
+    Stream::Closed => Err("attempt to use a closed file".into()),
+    _ => Err("cannot seek a standard stream".into())
+  }
+}
+
+fn file_flush(_: &mut VM, stream: &Shared) -> Result<Value, RuntimeError> {
+  let res = match &mut *stream.borrow_mut() {
+    Stream::File(file) => file.get_mut().flush(),
+    Stream::Stdout => io::stdout().flush(),
+    Stream::Stderr => io::stderr().flush(),
+    Stream::Stdin => Ok(()),
+    Stream::Closed => return Err("attempt to use a closed file".into())
+  };
+
+  res.map_err(io_error)?;
+
+  Ok(Value::Nil)
+}
+
+/// closes the file, using the handle after this is an error
+fn file_close(_: &mut VM, stream: &Shared) -> Result<Value, RuntimeError> {
+  let mut stream = stream.borrow_mut();
+
+  if let Stream::Closed = *stream {
+    return Err("attempt to use a closed file".into())
+  }
+
+  if let Stream::File(file) = &mut *stream {
+    file.get_mut().flush().map_err(io_error)?;
+  }
+
+  *stream = Stream::Closed;
+
+  Ok(Value::Nil)
+}
@@ -0,0 +1,524 @@
+use std::collections::HashSet;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::rc::Rc;
+
+use crate::common::{ Value, Table, Array, Type };
+use crate::vm::{ VM, env::{ Env, aux::* }, RuntimeError };
+use crate::{ expect, expect_any, optional };
+
+/// how deep arrays and objects can be nested when encoding or decoding
+const MAX_DEPTH: usize = 200;
+
+thread_local! {
+  /// `json.null`, `Table` can't hold nil so decoded nulls are this instead.
+  /// Its `__newindex` raises so it stays empty and `__metatable` keeps the
+  /// metatable from being changed.
+  static NULL: Table = {
+    let null = Table::new();
+    let meta = Table::new();
+
+    tbl_builtin(&meta, "__newindex", &null_newindex);
+    meta.insert(Value::String("__metatable".into()), Value::Bool(false)).unwrap();
+    meta.insert(Value::String("__name".into()), Value::String("json.null".into())).unwrap();
+    null.set_metatable(Some(meta));
+
+    null
+  };
+}
+
+pub fn load(env: &mut Env) {
+  let tbl = Table::new();
+
+  tbl_builtin(&tbl, "encode", &json_encode);
+  tbl_builtin(&tbl, "decode", &json_decode);
+  tbl.insert(Value::String("null".into()), Value::Table(NULL.with(Table::clone))).unwrap();
+
+  env.set_global(Value::String("json".into()), Value::Table(tbl))
+}
+
+fn null_newindex(_vm: &mut VM) -> Result<Value, RuntimeError> {
+  Err("attempt to modify json.null".into())
+}
+
+pub fn is_null(val: &Value) -> bool {
+  match val {
+    Value::Table(t) => NULL.with(| null | Rc::ptr_eq(&t.tbl, &null.tbl)),
+    _ => false
+  }
+}
+
+struct Encoder {
+  out: String,
+  pretty: bool,
+  sort_keys: bool,
+  /// the arrays and tables being encoded, to find cycles
+  path: Vec<usize>
+}
+
+/// `json.encode(value, {pretty, sort_keys})`, `pretty` indents with two
+/// spaces
+fn json_encode(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let val = expect_any!(vm);
+  let opts = optional!(Table, Table::new(), vm);
+
+  let mut enc = Encoder {
+    out: String::new(),
+    pretty: opts.get(&Value::String("pretty".into()))?.truthy(),
+    sort_keys: opts.get(&Value::String("sort_keys".into()))?.truthy(),
+    path: Vec::new()
+  };
+
+  enc.value(&val).map_err(| e | format!("bad argument #1 ({})", e))?;
+
+  Ok(Value::String(enc.out.into()))
+}
+
+impl Encoder {
+  fn value(&mut self, val: &Value) -> Result<(), String> {
+    match val {
+      Value::Nil => self.out.push_str("null"),
+      _ if is_null(val) => self.out.push_str("null"),
+
+      Value::Bool(b) => self.out.push_str(&b.to_string()),
+      Value::Integer(n) => self.out.push_str(&n.to_string()),
+      Value::Number(n) if n.is_finite() => self.out.push_str(&format!("{:?}", n)),
+      Value::Number(n) => return Err(format!("cannot encode {} as JSON", n)),
+      Value::String(s) => self.string(s),
+
+      Value::Array(a) => {
+        let vals = a.vec.borrow().clone();
+        let ptr = Rc::as_ptr(&a.vec) as usize;
+
+        self.container(ptr, ('[', ']'), vals.len(), | enc, i | enc.value(&vals[i]))?;
+      }
+
+      Value::Table(t) => {
+        let mut entries = Vec::with_capacity(t.len());
+        let mut keys = HashSet::with_capacity(t.len());
+
+        for (k, v) in t.tbl.borrow().iter() {
+          let key = match k {
+            Value::String(s) => s.to_string(),
+            Value::Integer(..) | Value::Number(..) => k.to_string(),
+
+            _ => return Err(format!("cannot encode a {:?} key as JSON", Type::from(k)))
+          };
+
+          // `t[1]` and `t["1"]` would both be `"1"`
+          if !keys.insert(key.clone()) {
+            return Err(format!("more than one key encodes as \"{}\"", key))
+          }
+
+          entries.push((key, v.clone()));
+        }
+
+        if self.sort_keys {
+          entries.sort_by(| (a, _), (b, _) | a.cmp(b));
+        }
+
+        let ptr = Rc::as_ptr(&t.tbl) as usize;
+
+        self.container(ptr, ('{', '}'), entries.len(), | enc, i | {
+          let (k, v) = &entries[i];
+
+          enc.string(k);
+          enc.out.push_str(if enc.pretty { ": " } else { ":" });
+          enc.value(v)
+        })?;
+      }
+
+      Value::Closure(..) | Value::NativeFunc(..) => return Err("cannot encode a function as JSON".into())
+    }
+
+    Ok(())
+  }
+
+  fn container<F>(&mut self, ptr: usize, (open, close): (char, char), len: usize, mut item: F) -> Result<(), String>
+    where F: FnMut(&mut Self, usize) -> Result<(), String> {
+    if self.path.contains(&ptr) {
+      return Err("cannot encode a cyclic structure as JSON".into())
+    }
+
+    if self.path.len() >= MAX_DEPTH {
+      return Err("too many nested arrays and objects".into())
+    }
+
+    self.path.push(ptr);
+    self.out.push(open);
+
+    for i in 0 .. len {
+      if i > 0 { self.out.push(',') }
+      self.newline();
+      item(self, i)?;
+    }
+
+    self.path.pop();
+
+    if len > 0 { self.newline() }
+    self.out.push(close);
+
+    Ok(())
+  }
+
+  fn newline(&mut self) {
+    if self.pretty {
+      self.out.push('\n');
+      self.out.push_str(&"  ".repeat(self.path.len()));
+    }
+  }
+
+  fn string(&mut self, s: &str) {
+    self.out.push('"');
+
+    for c in s.chars() {
+      match c {
+        '"' => self.out.push_str("\\\""),
+        '\\' => self.out.push_str("\\\\"),
+        '\n' => self.out.push_str("\\n"),
+        '\r' => self.out.push_str("\\r"),
+        '\t' => self.out.push_str("\\t"),
+        '\u{8}' => self.out.push_str("\\b"),
+        '\u{c}' => self.out.push_str("\\f"),
+        c if c.is_control() => self.out.push_str(&format!("\\u{:04x}", c as u32)),
+
+        c => self.out.push(c)
+      }
+    }
+
+    self.out.push('"');
+  }
+}
+
+struct Decoder<'a> {
+  chars: Peekable<Chars<'a>>,
+  line: usize,
+  col: usize,
+  depth: usize
+}
+
+/// `json.decode(str)`, objects become tables, arrays become arrays and null
+/// becomes `json.null`
+fn json_decode(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let str = expect!(String, vm)?;
+
+  let mut dec = Decoder {
+    chars: str.chars().peekable(),
+    line: 1,
+    col: 1,
+    depth: 0
+  };
+
+  let res = dec.value().and_then(| val | {
+    dec.skip_whitespace();
+
+    match dec.chars.peek() {
+      None => Ok(val),
+      Some(_) => Err(dec.error("unexpected data after the value"))
+    }
+  });
+
+  res.map_err(| e | format!("bad argument #1 ({})", e).into())
+}
+
+impl<'a> Decoder<'a> {
+  fn error(&self, err: &str) -> String {
+    format!("{} at line {} column {}", err, self.line, self.col)
+  }
+
+  fn next(&mut self) -> Option<char> {
+    let c = self.chars.next()?;
+
+    if c == '\n' {
+      self.line += 1;
+      self.col = 1;
+    } else {
+      self.col += 1;
+    }
+
+    Some(c)
+  }
+
+  fn skip_whitespace(&mut self) {
+    while matches!(self.chars.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+      self.next();
+    }
+  }
+
+  fn expect(&mut self, c: char) -> Result<(), String> {
+    match self.chars.peek().copied() {
+      Some(c2) if c2 == c => {
+        self.next();
+        Ok(())
+      }
+
+      Some(c2) => Err(self.error(&format!("expected '{}' got '{}'", c, c2))),
+      None => Err(self.error(&format!("expected '{}' got the end of the input", c)))
+    }
+  }
+
+  fn value(&mut self) -> Result<Value, String> {
+    self.skip_whitespace();
+
+    match self.chars.peek().copied() {
+      Some('{') => self.nested(Self::object),
+      Some('[') => self.nested(Self::array),
+      Some('"') => Ok(Value::String(self.string()?.into())),
+      Some('-' | '0' ..= '9') => self.number(),
+      Some('t') => self.literal("true", Value::Bool(true)),
+      Some('f') => self.literal("false", Value::Bool(false)),
+      Some('n') => self.literal("null", Value::Table(NULL.with(Table::clone))),
+
+      Some(c) => Err(self.error(&format!("unexpected character '{}'", c))),
+      None => Err(self.error("unexpected end of the input"))
+    }
+  }
+
+  fn nested<F>(&mut self, f: F) -> Result<Value, String>
+    where F: FnOnce(&mut Self) -> Result<Value, String> {
+    if self.depth >= MAX_DEPTH {
+      return Err(self.error("too many nested arrays and objects"))
+    }
+
+    self.depth += 1;
+    let res = f(self);
+    self.depth -= 1;
+
+    res
+  }
+
+  fn literal(&mut self, name: &str, val: Value) -> Result<Value, String> {
+    for c in name.chars() {
+      if self.chars.peek() != Some(&c) {
+        return Err(self.error(&format!("invalid literal, expected '{}'", name)))
+      }
+
+      self.next();
+    }
+
+    Ok(val)
+  }
+
+  fn object(&mut self) -> Result<Value, String> {
+    let tbl = Table::new();
+    self.next();
+    self.skip_whitespace();
+
+    if self.chars.peek() == Some(&'}') {
+      self.next();
+      return Ok(Value::Table(tbl))
+    }
+
+    loop {
+      self.skip_whitespace();
+
+      if self.chars.peek() != Some(&'"') {
+        return Err(self.error("expected a string key"))
+      }
+
+      let key = self.string()?;
+
+      self.skip_whitespace();
+      self.expect(':')?;
+
+      let val = self.value()?;
+      tbl.insert(Value::String(key.into()), val).unwrap();
+
+      self.skip_whitespace();
+
+      match self.chars.peek() {
+        Some(',') => { self.next(); }
+        _ => break
+      }
+    }
+
+    self.expect('}')?;
+    Ok(Value::Table(tbl))
+  }
+
+  fn array(&mut self) -> Result<Value, String> {
+    let mut vals = Vec::new();
+    self.next();
+    self.skip_whitespace();
+
+    if self.chars.peek() == Some(&']') {
+      self.next();
+      return Ok(Value::Array(Array::new(vals)))
+    }
+
+    loop {
+      vals.push(self.value()?);
+      self.skip_whitespace();
+
+      match self.chars.peek() {
+        Some(',') => { self.next(); }
+        _ => break
+      }
+    }
+
+    self.expect(']')?;
+    Ok(Value::Array(Array::new(vals)))
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    let mut str = String::new();
+    self.next();
+
+    // errors point at the character, so it's only read once it's known to be
+    // fine
+    loop {
+      let c = match self.chars.peek().copied() {
+        Some(c) => c,
+        None => return Err(self.error("unfinished string"))
+      };
+
+      match c {
+        '"' => {
+          self.next();
+          return Ok(str)
+        }
+
+        '\\' => {
+          self.next();
+
+          let c = match self.chars.peek() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+
+            Some('u') => {
+              self.next();
+              str.push(self.unicode_escape()?);
+              continue
+            }
+
+            _ => return Err(self.error("invalid escape sequence"))
+          };
+
+          self.next();
+          str.push(c);
+        }
+
+        c if c.is_control() => return Err(self.error("control character in string")),
+
+        c => {
+          self.next();
+          str.push(c);
+        }
+      }
+    }
+  }
+
+  /// `\uXXXX`, characters outside the BMP are written as a surrogate pair
+  fn unicode_escape(&mut self) -> Result<char, String> {
+    let high = self.hex4()?;
+
+    let code = if (0xd800 .. 0xdc00).contains(&high) {
+      for c in ['\\', 'u'] {
+        if self.chars.peek() != Some(&c) {
+          return Err(self.error("missing low surrogate"))
+        }
+
+        self.next();
+      }
+
+      let low = self.hex4()?;
+
+      if !(0xdc00 .. 0xe000).contains(&low) {
+        return Err(self.error("invalid low surrogate"))
+      }
+
+      0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+    } else {
+      high
+    };
+
+    std::char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+  }
+
+  fn hex4(&mut self) -> Result<u32, String> {
+    let mut n = 0;
+
+    for _ in 0 .. 4 {
+      match self.chars.peek().and_then(| c | c.to_digit(16)) {
+        Some(digit) => n = n * 16 + digit,
+        None => return Err(self.error("invalid unicode escape"))
+      }
+
+      self.next();
+    }
+
+    Ok(n)
+  }
+
+  /// integers stay integers unless they're too big for one
+  fn number(&mut self) -> Result<Value, String> {
+    let mut str = String::new();
+    let mut float = false;
+
+    if self.chars.peek() == Some(&'-') {
+      str.push('-');
+      self.next();
+    }
+
+    match self.chars.peek() {
+      Some('0') => {
+        str.push('0');
+        self.next();
+      }
+
+      Some('1' ..= '9') => self.digits(&mut str),
+      _ => return Err(self.error("invalid number"))
+    }
+
+    if self.chars.peek() == Some(&'.') {
+      float = true;
+      str.push('.');
+      self.next();
+
+      if !matches!(self.chars.peek(), Some('0' ..= '9')) {
+        return Err(self.error("missing digits after '.'"))
+      }
+
+      self.digits(&mut str);
+    }
+
+    if matches!(self.chars.peek(), Some('e' | 'E')) {
+      float = true;
+      str.push('e');
+      self.next();
+
+      if let Some(c @ ('+' | '-')) = self.chars.peek().copied() {
+        str.push(c);
+        self.next();
+      }
+
+      if !matches!(self.chars.peek(), Some('0' ..= '9')) {
+        return Err(self.error("missing exponent digits"))
+      }
+
+      self.digits(&mut str);
+    }
+
+    if !float {
+      if let Ok(n) = str.parse() {
+        return Ok(Value::Integer(n))
+      }
+    }
+
+    // the digits were checked so this can't fail
+    Ok(Value::Number(str.parse().unwrap()))
+  }
+
+  fn digits(&mut self, str: &mut String) {
+    while let Some(c @ '0' ..= '9') = self.chars.peek().copied() {
+      str.push(c);
+      self.next();
+    }
+  }
+}
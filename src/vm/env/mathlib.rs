@@ -4,25 +4,30 @@ use std::time::UNIX_EPOCH;
 use std::f64::consts::PI;
 
 use crate::vm::{ VM, env::{ Env, aux::* }, RuntimeError };
-use crate::common::{ Value, Table };
-use crate::{ expect, arg_check };
+use crate::common::{ Value, Table, Type };
+use crate::{ expect, expect_any, arg_check };
 
 macro_rules! max_min {
   ($op:tt, $vm:ident) => {{
     let vm = $vm;
 
-    let mut final_max = expect!(Number, vm)?;
+    let mut final_max = expect_any!(vm);
     let narg = vm.nci.top - vm.nci.base;
 
-    for _ in 0..narg {
-      let n = expect!(Number, vm)?;
+    arg_check!(final_max.to_float().is_some(), 1, format!("expected number got {:?}", Type::from(&final_max)))?;
+
+    for i in 0..narg {
+      let n = expect_any!(vm);
+
+      arg_check!(n.to_float().is_some(), i + 2, format!("expected number got {:?}", Type::from(&n)))?;
 
       if n $op final_max {
         final_max = n
       }
     }
 
-    Ok(Value::Number(final_max))
+    // integers stay integers
+    Ok(final_max)
   }};
 }
 
@@ -34,6 +39,23 @@ macro_rules! method {
   }};
 }
 
+/// like `method!` but the result is an integer when it fits in one
+macro_rules! int_method {
+  ($id:ident, $vm:ident) => {{
+    let val = expect_any!($vm);
+
+    match val {
+      Value::Integer(..) => Ok(val),
+      Value::Number(n) => {
+        let n = Value::Number(n.$id());
+        Ok(n.to_integer().map(Value::Integer).unwrap_or(n))
+      }
+
+      _ => Err(format!("bad argument #1 (expected number got {:?})", Type::from(&val)).into())
+    }
+  }};
+}
+
 macro_rules! method2 {
   ($id:ident, $vm:ident) => {{
     let n = expect!(Number, $vm)?;
@@ -59,15 +81,19 @@ pub fn load(env: &mut Env) {
   tbl_builtin(&tbl, "max", &math_max);
   tbl_builtin(&tbl, "min", &math_min);
   tbl_builtin(&tbl, "abs", &math_abs);
+  tbl_builtin(&tbl, "type", &math_type);
+  tbl_builtin(&tbl, "tointeger", &math_tointeger);
 
   tbl.insert(Value::String("pi".into()), Value::Number(PI)).unwrap();
   tbl.insert(Value::String("huge".into()), Value::Number(f64::INFINITY)).unwrap();
+  tbl.insert(Value::String("maxinteger".into()), Value::Integer(i64::MAX)).unwrap();
+  tbl.insert(Value::String("mininteger".into()), Value::Integer(i64::MIN)).unwrap();
 
   env.set_global(Value::String("math".into()), Value::Table(tbl))
 }
 
 fn math_floor(vm: &mut VM) -> Result<Value, RuntimeError> {
-  method!(floor, vm)
+  int_method!(floor, vm)
 }
 
 fn math_sqrt(vm: &mut VM) -> Result<Value, RuntimeError> {
@@ -75,11 +101,35 @@ fn math_sqrt(vm: &mut VM) -> Result<Value, RuntimeError> {
 }
 
 fn math_abs(vm: &mut VM) -> Result<Value, RuntimeError> {
-  method!(abs, vm)
+  let val = expect_any!(vm);
+
+  match val {
+    Value::Integer(n) => Ok(Value::Integer(n.wrapping_abs())),
+    Value::Number(n) => Ok(Value::Number(n.abs())),
+
+    _ => Err(format!("bad argument #1 (expected number got {:?})", Type::from(&val)).into())
+  }
 }
 
 fn math_ceil(vm: &mut VM) -> Result<Value, RuntimeError> {
-  method!(ceil, vm)
+  int_method!(ceil, vm)
+}
+
+fn math_type(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let val = expect_any!(vm);
+
+  match val {
+    Value::Integer(..) => Ok(Value::String("integer".into())),
+    Value::Number(..) => Ok(Value::String("float".into())),
+
+    _ => Ok(Value::Nil)
+  }
+}
+
+fn math_tointeger(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let val = expect_any!(vm);
+
+  Ok(val.to_integer().map(Value::Integer).unwrap_or(Value::Nil))
 }
 
 fn math_log10(vm: &mut VM) -> Result<Value, RuntimeError> {
@@ -109,20 +159,23 @@ fn math_random(vm: &mut VM) -> Result<Value, RuntimeError> {
     0 => Ok(Value::Number(r)),
 
     1 => {
-      let u = expect!(Number, vm)?;
+      let u = expect!(Integer, vm)?;
 
-      arg_check!(1.0 <= u, 1, "interval is empty")?;
+      arg_check!(1 <= u, 1, "interval is empty")?;
 
-      Ok(Value::Number((r * u).floor() + 1.0))
+      Ok(Value::Integer((r * u as f64).floor() as i64 + 1))
     }
 
     2 => {
-      let l = expect!(Number, vm)?;
-      let u = expect!(Number, vm)?;
+      let l = expect!(Integer, vm)?;
+      let u = expect!(Integer, vm)?;
 
       arg_check!(l <= u, 2, "interval is empty")?;
 
-      Ok(Value::Number((r * (u - l + 1.0)).floor() + l))
+      let range = u.checked_sub(l).and_then(| n | n.checked_add(1));
+      let range = range.ok_or("bad argument #2 (interval is too large)")?;
+
+      Ok(Value::Integer((r * range as f64).floor() as i64 + l))
     }
 
     _ => Err("wrong number of arguments".into())
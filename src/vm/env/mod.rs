@@ -2,10 +2,18 @@ use crate::common::{ Value, BuiltIn, Table };
 use aux::tbl_builtin;
 
 pub mod aux;
+mod arraylib;
 mod globals;
+mod iolib;
+mod format;
+mod jsonlib;
 mod mathlib;
+mod oslib;
+mod pattern;
 mod require;
 mod strlib;
+mod tablelib;
+mod utf8lib;
 
 pub struct Env {
   pub globals: Table
@@ -25,7 +33,13 @@ impl Env {
   pub fn load(&mut self) {
     globals::load(self);
     strlib::load(self);
+    utf8lib::load(self);
     mathlib::load(self);
+    arraylib::load(self);
+    tablelib::load(self);
+    jsonlib::load(self);
+    iolib::load(self);
+    oslib::load(self);
 
     self.builtin("require", &require::require);
     self.set_global(Value::String("_G".into()), Value::Table(self.globals.clone()))
@@ -0,0 +1,148 @@
+use std::time::{ SystemTime, UNIX_EPOCH };
+use std::convert::TryFrom;
+use std::io::{ self, Write };
+use std::process::exit;
+use std::fs::{ self, OpenOptions };
+use std::env;
+
+use crate::common::{ Value, Table, Type };
+use crate::vm::{ VM, env::{ Env, aux::*, iolib::io_error }, RuntimeError };
+use crate::expect;
+
+pub fn load(env: &mut Env) {
+  let tbl = Table::new();
+
+  tbl_builtin(&tbl, "getenv", &os_getenv);
+  tbl_builtin(&tbl, "setenv", &os_setenv);
+  tbl_builtin(&tbl, "time", &os_time);
+  tbl_builtin(&tbl, "clock", &os_clock);
+  tbl_builtin(&tbl, "exit", &os_exit);
+  tbl_builtin(&tbl, "remove", &os_remove);
+  tbl_builtin(&tbl, "rename", &os_rename);
+  tbl_builtin(&tbl, "tmpname", &os_tmpname);
+
+  env.set_global(Value::String("os".into()), Value::Table(tbl))
+}
+
+/// nil if the variable isn't set or isn't valid unicode
+fn os_getenv(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let name = expect!(String, vm)?;
+
+  match env::var(&*name) {
+    Ok(val) => Ok(Value::String(val.into())),
+    Err(..) => Ok(Value::Nil)
+  }
+}
+
+/// `setenv(name, nil)` removes the variable
+fn os_setenv(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let name = expect!(String, vm)?;
+  let val = try_get(vm).unwrap_or(Value::Nil);
+
+  if name.is_empty() || name.contains(&['=', '\0'][..]) {
+    return Err("bad argument #1 (invalid variable name)".into())
+  }
+
+  match val {
+    Value::Nil => env::remove_var(&*name),
+    Value::String(s) if !s.contains('\0') => env::set_var(&*name, &*s),
+    Value::Integer(..) | Value::Number(..) => env::set_var(&*name, val.to_string()),
+
+    _ => return Err(format!("bad argument #2 (expected string got {:?})", Type::from(&val)).into())
+  }
+
+  Ok(Value::Nil)
+}
+
+/// seconds since the Unix epoch
+fn os_time(_vm: &mut VM) -> Result<Value, RuntimeError> {
+  let time = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .expect("Time went backwards")
+    .as_secs();
+
+  Ok(Value::Integer(time as i64))
+}
+
+/// the CPU time used by the process in seconds, unlike the wall clock time
+/// of `clock`
+fn os_clock(_vm: &mut VM) -> Result<Value, RuntimeError> {
+  #[cfg(unix)]
+  {
+    let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+
+    if unsafe { libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, &mut time) } != 0 {
+      return Err("unable to get the CPU time".into())
+    }
+
+    Ok(Value::Number(time.tv_sec as f64 + time.tv_nsec as f64 / 1e9))
+  }
+
+  #[cfg(not(unix))]
+  Err("os.clock is only supported on unix".into())
+}
+
+/// `exit(code)` where code is an integer or a bool, true is 0 and false 1.
+/// The `__gc` metamethods are run first like when the script ends.
+fn os_exit(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let code = try_get(vm).unwrap_or(Value::Integer(0));
+
+  let code = match code {
+    Value::Bool(ok) => if ok { 0 } else { 1 },
+
+    _ => match code.to_integer() {
+      Some(n) => i32::try_from(n).map_err(| _ | "bad argument #1 (exit code out of range)")?,
+      None if matches!(code, Value::Number(..)) => return Err("bad argument #1 (number has no integer representation)".into()),
+      None => return Err(format!("bad argument #1 (expected number got {:?})", Type::from(&code)).into())
+    }
+  };
+
+  // a finalizer that fails doesn't stop the exit
+  vm.finalize_all().ok();
+
+  io::stdout().flush().ok();
+  exit(code)
+}
+
+/// removes a file or an empty directory
+fn os_remove(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let path = expect!(String, vm)?;
+
+  let res = match fs::metadata(&*path) {
+    Ok(meta) if meta.is_dir() => fs::remove_dir(&*path),
+    _ => fs::remove_file(&*path)
+  };
+
+  res.map_err(| e | format!("{}: {}", path, io_error(e)))?;
+
+  Ok(Value::Nil)
+}
+
+fn os_rename(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let from = expect!(String, vm)?;
+  let to = expect!(String, vm)?;
+
+  fs::rename(&*from, &*to).map_err(| e | format!("{}: {}", from, io_error(e)))?;
+
+  Ok(Value::Nil)
+}
+
+/// creates an empty file in the temporary directory and returns its path,
+/// creating it makes sure no one else gets the same name
+fn os_tmpname(_vm: &mut VM) -> Result<Value, RuntimeError> {
+  let dir = env::temp_dir();
+  let pid = std::process::id();
+
+  for i in 0 .. 100 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(| d | d.subsec_nanos()).unwrap_or(0);
+    let path = dir.join(format!("moon_{}_{:x}{}", pid, nanos, i));
+
+    match OpenOptions::new().write(true).create_new(true).open(&path) {
+      Ok(..) => return Ok(Value::String(path.to_string_lossy().into_owned().into())),
+      Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+      Err(e) => return Err(io_error(e).into())
+    }
+  }
+
+  Err("unable to create a temporary file".into())
+}
@@ -0,0 +1,533 @@
+use std::cell::{ Cell, RefCell };
+
+use crate::common::{ Value, Array, Type };
+use crate::vm::{ VM, env::aux::*, RuntimeError };
+use crate::{ expect, expect_any, optional, arg_check };
+
+/// Lua patterns, matched on code points instead of bytes:
+///
+/// - `.` any character, `%a %c %d %g %l %p %s %u %w %x` classes and their
+///   uppercase complements, `%` followed by anything else escapes it
+/// - `[set]` and `[^set]` with ranges like `a-z` and classes
+/// - `*` `+` `-` (lazy) and `?` quantifiers, `^` and `$` anchors
+/// - `(...)` captures, `()` captures the position, `%1`-`%9` back references,
+///   `%bxy` balanced pairs and `%f[set]` frontiers
+const MAX_CAPTURES: usize = 32;
+const MAX_DEPTH: usize = 200;
+
+#[derive(Clone, Copy, PartialEq)]
+enum CapLen {
+  Len(usize),
+  Position,
+  Unclosed
+}
+
+pub enum Capture {
+  Str(String),
+  Position(usize)
+}
+
+struct Matcher<'a> {
+  src: &'a [char],
+  pat: &'a [char],
+  captures: Vec<(usize, CapLen)>,
+  depth: usize
+}
+
+impl<'a> Matcher<'a> {
+  fn new(src: &'a [char], pat: &'a [char]) -> Self {
+    Matcher {
+      src,
+      pat,
+      captures: Vec::new(),
+      depth: 0
+    }
+  }
+
+  /// tries to match the pattern starting at `pat[p]` against `src[s..]`,
+  /// returns where the match ends
+  fn do_match(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, String> {
+    self.depth += 1;
+
+    if self.depth > MAX_DEPTH {
+      return Err("pattern too complex".into())
+    }
+
+    let res = loop {
+      if p == self.pat.len() { break Some(s) }
+
+      match self.pat[p] {
+        '(' if self.pat.get(p + 1) == Some(&')') => break self.start_capture(s, p + 2, CapLen::Position)?,
+        '(' => break self.start_capture(s, p + 1, CapLen::Unclosed)?,
+        ')' => break self.end_capture(s, p + 1)?,
+
+        '$' if p + 1 == self.pat.len() => break if s == self.src.len() { Some(s) } else { None },
+
+        '%' if self.pat.get(p + 1) == Some(&'b') => match self.match_balance(s, p + 2)? {
+          Some(end) => {
+            s = end;
+            p += 4;
+          }
+
+          None => break None
+        },
+
+        '%' if self.pat.get(p + 1) == Some(&'f') => {
+          p += 2;
+
+          if self.pat.get(p) != Some(&'[') {
+            return Err("missing '[' after '%f' in pattern".into())
+          }
+
+          let ep = self.class_end(p)?;
+          let prev = if s == 0 { '\0' } else { self.src[s - 1] };
+          let cur = self.src.get(s).copied().unwrap_or('\0');
+
+          if !self.match_set(prev, p, ep - 1) && self.match_set(cur, p, ep - 1) {
+            p = ep;
+          } else {
+            break None
+          }
+        }
+
+        '%' if matches!(self.pat.get(p + 1), Some(c) if c.is_ascii_digit()) => {
+          match self.match_capture(s, self.pat[p + 1])? {
+            Some(end) => {
+              s = end;
+              p += 2;
+            }
+
+            None => break None
+          }
+        }
+
+        _ => {
+          let ep = self.class_end(p)?;
+          let matched = s < self.src.len() && self.single_match(self.src[s], p, ep);
+
+          match self.pat.get(ep) {
+            Some('?') => {
+              if matched {
+                if let Some(end) = self.do_match(s + 1, ep + 1)? { break Some(end) }
+              }
+
+              p = ep + 1;
+            }
+
+            Some('+') => break if matched { self.max_expand(s + 1, p, ep)? } else { None },
+            Some('*') => break self.max_expand(s, p, ep)?,
+            Some('-') => break self.min_expand(s, p, ep)?,
+
+            _ if matched => {
+              s += 1;
+              p = ep;
+            }
+
+            _ => break None
+          }
+        }
+      }
+    };
+
+    self.depth -= 1;
+    Ok(res)
+  }
+
+  /// returns the index right after the single character class at `p`
+  fn class_end(&self, mut p: usize) -> Result<usize, String> {
+    let c = self.pat[p];
+    p += 1;
+
+    match c {
+      '%' => {
+        if p >= self.pat.len() {
+          return Err("malformed pattern (ends with '%')".into())
+        }
+
+        Ok(p + 1)
+      }
+
+      '[' => {
+        if self.pat.get(p) == Some(&'^') { p += 1 }
+
+        // the first character can be a `]`
+        loop {
+          if p >= self.pat.len() {
+            return Err("malformed pattern (missing ']')".into())
+          }
+
+          let c = self.pat[p];
+          p += 1;
+
+          if c == '%' { p += 1 }
+          if self.pat.get(p) == Some(&']') { break }
+        }
+
+        Ok(p + 1)
+      }
+
+      _ => Ok(p)
+    }
+  }
+
+  fn single_match(&self, c: char, p: usize, ep: usize) -> bool {
+    match self.pat[p] {
+      '.' => true,
+      '%' => match_class(c, self.pat[p + 1]),
+      '[' => self.match_set(c, p, ep - 1),
+      pc => pc == c
+    }
+  }
+
+  /// `p` is the `[` of the set and `end` its `]`
+  fn match_set(&self, c: char, mut p: usize, end: usize) -> bool {
+    let mut neg = false;
+    p += 1;
+
+    if self.pat[p] == '^' {
+      neg = true;
+      p += 1;
+    }
+
+    while p < end {
+      if self.pat[p] == '%' {
+        p += 1;
+        if match_class(c, self.pat[p]) { return !neg }
+        p += 1;
+      } else if self.pat.get(p + 1) == Some(&'-') && p + 2 < end {
+        if self.pat[p] <= c && c <= self.pat[p + 2] { return !neg }
+        p += 3;
+      } else {
+        if self.pat[p] == c { return !neg }
+        p += 1;
+      }
+    }
+
+    neg
+  }
+
+  fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, String> {
+    let mut i = 0;
+
+    while s + i < self.src.len() && self.single_match(self.src[s + i], p, ep) {
+      i += 1;
+    }
+
+    // try the longest run first
+    loop {
+      if let Some(end) = self.do_match(s + i, ep + 1)? { return Ok(Some(end)) }
+      if i == 0 { return Ok(None) }
+      i -= 1;
+    }
+  }
+
+  fn min_expand(&mut self, mut s: usize, p: usize, ep: usize) -> Result<Option<usize>, String> {
+    loop {
+      if let Some(end) = self.do_match(s, ep + 1)? {
+        return Ok(Some(end))
+      } else if s < self.src.len() && self.single_match(self.src[s], p, ep) {
+        s += 1;
+      } else {
+        return Ok(None)
+      }
+    }
+  }
+
+  fn start_capture(&mut self, s: usize, p: usize, len: CapLen) -> Result<Option<usize>, String> {
+    if self.captures.len() >= MAX_CAPTURES {
+      return Err("too many captures".into())
+    }
+
+    self.captures.push((s, len));
+    let res = self.do_match(s, p)?;
+
+    if res.is_none() {
+      self.captures.pop();
+    }
+
+    Ok(res)
+  }
+
+  fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+    let idx = self.captures.iter()
+      .rposition(| (_, len) | *len == CapLen::Unclosed)
+      .ok_or("invalid pattern capture")?;
+
+    self.captures[idx].1 = CapLen::Len(s - self.captures[idx].0);
+    let res = self.do_match(s, p)?;
+
+    if res.is_none() {
+      self.captures[idx].1 = CapLen::Unclosed;
+    }
+
+    Ok(res)
+  }
+
+  fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, String> {
+    let (open, close) = match (self.pat.get(p), self.pat.get(p + 1)) {
+      (Some(open), Some(close)) => (*open, *close),
+      _ => return Err("malformed pattern (missing arguments to '%b')".into())
+    };
+
+    if self.src.get(s) != Some(&open) {
+      return Ok(None)
+    }
+
+    let mut depth = 1;
+
+    for i in s + 1 .. self.src.len() {
+      let c = self.src[i];
+
+      if c == close {
+        depth -= 1;
+        if depth == 0 { return Ok(Some(i + 1)) }
+      } else if c == open {
+        depth += 1;
+      }
+    }
+
+    Ok(None)
+  }
+
+  fn match_capture(&self, s: usize, idx: char) -> Result<Option<usize>, String> {
+    let idx = idx.to_digit(10).unwrap() as usize;
+
+    let (start, len) = match self.captures.get(idx.wrapping_sub(1)) {
+      Some((start, CapLen::Len(len))) => (*start, *len),
+      _ => return Err(format!("invalid capture index %{}", idx))
+    };
+
+    let cap = &self.src[start .. start + len];
+
+    if self.src[s ..].starts_with(cap) {
+      Ok(Some(s + len))
+    } else {
+      Ok(None)
+    }
+  }
+
+  /// the captures of the last match, or the whole match if there are none
+  fn get_captures(&self, s: usize, e: usize, whole: bool) -> Result<Vec<Capture>, String> {
+    if self.captures.is_empty() && whole {
+      return Ok(vec![Capture::Str(self.src[s .. e].iter().collect())])
+    }
+
+    self.captures.iter().map(| (start, len) | match len {
+      CapLen::Len(len) => Ok(Capture::Str(self.src[*start .. start + len].iter().collect())),
+      CapLen::Position => Ok(Capture::Position(*start)),
+      CapLen::Unclosed => Err("unfinished capture".into())
+    }).collect()
+  }
+}
+
+fn match_class(c: char, class: char) -> bool {
+  let res = match class.to_ascii_lowercase() {
+    'a' => c.is_alphabetic(),
+    'c' => c.is_control(),
+    'd' => c.is_ascii_digit(),
+    'g' => !c.is_whitespace() && !c.is_control(),
+    'l' => c.is_lowercase(),
+    'p' => c.is_ascii_punctuation(),
+    's' => c.is_whitespace(),
+    'u' => c.is_uppercase(),
+    'w' => c.is_alphanumeric(),
+    'x' => c.is_ascii_hexdigit(),
+
+    _ => return class == c
+  };
+
+  if class.is_ascii_uppercase() { !res } else { res }
+}
+
+impl Capture {
+  fn into_value(self) -> Value {
+    match self {
+      Capture::Str(s) => Value::String(s.into()),
+      Capture::Position(pos) => Value::Integer(pos as i64)
+    }
+  }
+}
+
+/// a single capture is returned as is, more than one as an array
+fn captures_value(mut captures: Vec<Capture>) -> Value {
+  if captures.len() == 1 {
+    captures.pop().unwrap().into_value()
+  } else {
+    Value::Array(Array::new(captures.into_iter().map(Capture::into_value).collect()))
+  }
+}
+
+fn pattern_error(e: String) -> RuntimeError {
+  format!("bad argument #2 ({})", e).into()
+}
+
+/// `string.match(s, pattern, init?)` returns the match, the capture, or an
+/// array with the captures if there are more than one
+pub fn str_match(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let str = expect!(String, vm)?;
+  let pat = expect!(String, vm)?;
+  let init = optional!(Integer, 0, vm);
+
+  arg_check!(init >= 0, 3, "initial position must not be negative")?;
+
+  let src = str.chars().collect::<Vec<char>>();
+  let pat = pat.chars().collect::<Vec<char>>();
+
+  let (anchor, p) = if pat.first() == Some(&'^') { (true, 1) } else { (false, 0) };
+  let mut m = Matcher::new(&src, &pat);
+
+  for s in (init as usize).min(src.len()) ..= src.len() {
+    m.captures.clear();
+
+    if let Some(e) = m.do_match(s, p).map_err(pattern_error)? {
+      return Ok(captures_value(m.get_captures(s, e, true).map_err(pattern_error)?))
+    }
+
+    if anchor { break }
+  }
+
+  Ok(Value::Nil)
+}
+
+/// `string.gmatch(s, pattern)` returns a function that returns the next match
+/// every time it's called, and nil once there are no more
+pub fn str_gmatch(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let str = expect!(String, vm)?;
+  let pat = expect!(String, vm)?;
+
+  let src = str.chars().collect::<Vec<char>>();
+  let pat = pat.chars().collect::<Vec<char>>();
+  let pos = Cell::new(0);
+  let last_match = RefCell::new(None);
+
+  Ok(native_closure("gmatch iterator", move | _ | {
+    let mut m = Matcher::new(&src, &pat);
+
+    for s in pos.get() ..= src.len() {
+      m.captures.clear();
+
+      match m.do_match(s, 0).map_err(pattern_error)? {
+        // an empty match right after the last one would loop forever
+        Some(e) if Some(e) != *last_match.borrow() => {
+          pos.set(e);
+          last_match.replace(Some(e));
+
+          return Ok(captures_value(m.get_captures(s, e, true).map_err(pattern_error)?))
+        }
+
+        _ => {}
+      }
+    }
+
+    pos.set(src.len() + 1);
+    Ok(Value::Nil)
+  }))
+}
+
+/// `string.gsub(s, pattern, repl, n?)` replaces the first `n` matches, or all
+/// of them. `repl` can be a string where `%0`-`%9` are the captures, a table
+/// indexed by the first capture or a function called with the captures. If
+/// the table or function gives nil or false the match is kept.
+pub fn str_gsub(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let str = expect!(String, vm)?;
+  let pat = expect!(String, vm)?;
+  let repl = expect_any!(vm);
+  let max = optional!(Integer, i64::MAX, vm);
+
+  if !matches!(repl, Value::String(..) | Value::Integer(..) | Value::Number(..) | Value::Table(..) | Value::Closure(..) | Value::NativeFunc(..)) {
+    return Err(format!("bad argument #3 (expected string, table or function got {:?})", Type::from(&repl)).into())
+  }
+
+  let src = str.chars().collect::<Vec<char>>();
+  let pat = pat.chars().collect::<Vec<char>>();
+
+  let (anchor, p) = if pat.first() == Some(&'^') { (true, 1) } else { (false, 0) };
+
+  let mut out = String::with_capacity(str.len());
+  let mut s = 0;
+  let mut last_match = None;
+  let mut n = 0;
+
+  while n < max {
+    let mut m = Matcher::new(&src, &pat);
+
+    match m.do_match(s, p).map_err(pattern_error)? {
+      Some(e) if Some(e) != last_match => {
+        n += 1;
+
+        let whole = src[s .. e].iter().collect::<String>();
+        let captures = m.get_captures(s, e, true).map_err(pattern_error)?;
+
+        out.push_str(&replacement(vm, &repl, whole, captures)?);
+        s = e;
+        last_match = Some(e);
+      }
+
+      _ if s < src.len() => {
+        out.push(src[s]);
+        s += 1;
+      }
+
+      _ => break
+    }
+
+    if anchor { break }
+  }
+
+  out.extend(&src[s.min(src.len()) ..]);
+
+  Ok(Value::String(out.into()))
+}
+
+fn replacement(vm: &mut VM, repl: &Value, whole: String, mut captures: Vec<Capture>) -> Result<String, RuntimeError> {
+  let val = match repl {
+    Value::String(s) => return expand(s, &whole, &captures),
+    Value::Integer(..) | Value::Number(..) => return Ok(repl.to_string()),
+
+    Value::Table(t) => t.get(&captures.swap_remove(0).into_value())?,
+
+    _ => {
+      let args = captures.into_iter().map(Capture::into_value).collect();
+      vm.call_value(repl.clone(), args)?
+    }
+  };
+
+  match val {
+    Value::Nil | Value::Bool(false) => Ok(whole),
+    Value::String(..) | Value::Integer(..) | Value::Number(..) => Ok(val.to_string()),
+
+    _ => Err(format!("invalid replacement value (a {:?})", Type::from(&val)).into())
+  }
+}
+
+/// replaces `%0`-`%9` and `%%` in a replacement string
+fn expand(repl: &str, whole: &str, captures: &[Capture]) -> Result<String, RuntimeError> {
+  let mut out = String::with_capacity(repl.len());
+  let mut chars = repl.chars();
+
+  while let Some(c) = chars.next() {
+    if c != '%' {
+      out.push(c);
+      continue
+    }
+
+    match chars.next() {
+      Some('%') => out.push('%'),
+      Some('0') => out.push_str(whole),
+
+      Some(c) if c.is_ascii_digit() => {
+        let idx = c.to_digit(10).unwrap() as usize - 1;
+
+        match captures.get(idx) {
+          Some(Capture::Str(s)) => out.push_str(s),
+          Some(Capture::Position(pos)) => out.push_str(&pos.to_string()),
+          None => return Err(format!("invalid capture index %{} in replacement string", idx + 1).into())
+        }
+      }
+
+      _ => return Err("invalid use of '%' in replacement string".into())
+    }
+  }
+
+  Ok(out)
+}
@@ -61,10 +61,9 @@ pub fn require(vm: &mut VM) -> Result<Value, RuntimeError> {
     unsafe { load_from_dlib(vm, path) }
   } else {
     // normal file
-    let closure = compile_file(path)?;
+    let closure = compile_file(path.to_string())?;
 
-    vm.run_closure(closure)?;
-
-    Ok(Value::Nil)
+    // the module runs in its own frame and its return value is the result
+    vm.call_value(Value::Closure(closure), Vec::new())
   }
 }
\ No newline at end of file
@@ -1,6 +1,11 @@
-use crate::common::{ Value, Table, Array };
-use crate::vm::{ VM, env::{ Env, aux::* }, RuntimeError };
-use crate::{ expect, optional };
+use std::convert::TryFrom;
+
+use crate::common::{ Value, Table, Array, Type, Str };
+use crate::vm::{ VM, env::{ Env, aux::*, format::str_format, pattern::* }, RuntimeError };
+use crate::{ expect, optional, arg_check };
+
+/// longer results are an error instead of a failed allocation
+const MAX_STR_LEN: usize = 1 << 30;
 
 pub fn load(env: &mut Env) {
   let tbl = Table::new();
@@ -11,6 +16,23 @@ pub fn load(env: &mut Env) {
   tbl_builtin(&tbl, "trim", &str_trim);
   tbl_builtin(&tbl, "byte", &str_byte);
   tbl_builtin(&tbl, "sub", &str_sub);
+  tbl_builtin(&tbl, "bytes", &str_bytes);
+  tbl_builtin(&tbl, "bytelen", &str_bytelen);
+  tbl_builtin(&tbl, "find", &str_find);
+  tbl_builtin(&tbl, "replace", &str_replace);
+  tbl_builtin(&tbl, "repeat", &str_repeat);
+  tbl_builtin(&tbl, "reverse", &str_reverse);
+  tbl_builtin(&tbl, "starts_with", &str_starts_with);
+  tbl_builtin(&tbl, "ends_with", &str_ends_with);
+  tbl_builtin(&tbl, "contains", &str_contains);
+  tbl_builtin(&tbl, "pad_left", &str_pad_left);
+  tbl_builtin(&tbl, "pad_right", &str_pad_right);
+  tbl_builtin(&tbl, "char", &str_char);
+  tbl_builtin(&tbl, "join", &str_join);
+  tbl_builtin(&tbl, "format", &str_format);
+  tbl_builtin(&tbl, "match", &str_match);
+  tbl_builtin(&tbl, "gmatch", &str_gmatch);
+  tbl_builtin(&tbl, "gsub", &str_gsub);
 
   env.set_global(Value::String("string".into()), Value::Table(tbl))
 }
@@ -18,22 +40,22 @@ pub fn load(env: &mut Env) {
 fn str_upper(vm: &mut VM) -> Result<Value, RuntimeError> {
   let str = expect!(String, vm)?;
 
-  Ok(Value::String(str.to_uppercase()))
+  Ok(Value::String(str.to_uppercase().into()))
 }
 
 fn str_lower(vm: &mut VM) -> Result<Value, RuntimeError> {
   let str = expect!(String, vm)?;
 
-  Ok(Value::String(str.to_lowercase()))
+  Ok(Value::String(str.to_lowercase().into()))
 }
 
 fn str_split(vm: &mut VM) -> Result<Value, RuntimeError> {
   let str = expect!(String, vm)?;
-  let pat = optional!(String, " ".to_string(), vm);
+  let pat = optional!(String, " ".into(), vm);
   let mut vals = Vec::new();
 
-  for found in str.split(&pat) {
-    vals.push(Value::String(found.to_string()))
+  for found in str.split(pat.as_str()) {
+    vals.push(Value::String(found.to_string().into()))
   }
 
   Ok(Value::Array(Array::new(vals)))
@@ -42,26 +64,198 @@ fn str_split(vm: &mut VM) -> Result<Value, RuntimeError> {
 fn str_trim(vm: &mut VM) -> Result<Value, RuntimeError> {
   let str = expect!(String, vm)?;
 
-  Ok(Value::String(str.trim().to_string()))
+  Ok(Value::String(str.trim().to_string().into()))
 }
 
 fn str_byte(vm: &mut VM) -> Result<Value, RuntimeError> {
   let str = expect!(String, vm)?;
-  let pos = optional!(Number, 0.0, vm) as usize;
+  let pos = optional!(Integer, 0, vm) as usize;
 
-  Ok(Value::Number(str.bytes().nth(pos).unwrap_or(b'\0').into()))
+  Ok(Value::Integer(str.bytes().nth(pos).unwrap_or(b'\0').into()))
 }
 
+/// `start` and `end` are code point indices
 fn str_sub(vm: &mut VM) -> Result<Value, RuntimeError> {
   let str = expect!(String, vm)?;
-  let len = str.len();
+  let len = str.char_len();
+
+  let start = expect!(Integer, vm)?;
+  let end = optional!(Integer, len as i64, vm);
+
+  arg_check!(start >= 0, 2, "start must not be negative")?;
+  arg_check!(end >= 0, 3, "end must not be negative")?;
+
+  let end = (end as usize).min(len);
+  let start = (start as usize).min(end);
+
+  let (start, end) = (str.char_offset(start), str.char_offset(end));
+
+  Ok(Value::String(str[start..end].to_string().into()))
+}
+
+fn str_bytes(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let str = expect!(String, vm)?;
+  let bytes = str.bytes().map(| b | Value::Integer(b.into())).collect();
+
+  Ok(Value::Array(Array::new(bytes)))
+}
+
+fn str_bytelen(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let str = expect!(String, vm)?;
+
+  Ok(Value::Integer(str.len() as i64))
+}
+
+/// returns the code point index of the first `pat` starting at `init`, or nil
+fn str_find(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let str = expect!(String, vm)?;
+  let pat = expect!(String, vm)?;
+  let init = optional!(Integer, 0, vm);
+
+  arg_check!(init >= 0, 3, "initial position must not be negative")?;
+
+  let start = str.char_offset(init as usize);
+
+  match str[start ..].find(pat.as_str()) {
+    Some(pos) => Ok(Value::Integer((init as usize + str[start .. start + pos].chars().count()) as i64)),
+    None => Ok(Value::Nil)
+  }
+}
+
+/// replaces the first `count` matches, or all of them
+fn str_replace(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let str = expect!(String, vm)?;
+  let from = expect!(String, vm)?;
+  let to = expect!(String, vm)?;
+  let count = optional!(Integer, -1, vm);
+
+  arg_check!(!from.is_empty(), 2, "empty pattern")?;
+
+  if count < 0 {
+    Ok(Value::String(str.replace(from.as_str(), &to).into()))
+  } else {
+    Ok(Value::String(str.replacen(from.as_str(), &to, count as usize).into()))
+  }
+}
+
+fn str_repeat(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let str = expect!(String, vm)?;
+  let n = expect!(Integer, vm)?;
+  let sep = optional!(String, "".into(), vm);
+
+  arg_check!(n >= 0, 2, "count must not be negative")?;
+
+  let n = n as usize;
+  let len = str.len().checked_mul(n)
+    .and_then(| len | len.checked_add(sep.len().checked_mul(n.saturating_sub(1))?));
+
+  let len = check_len(len)?;
+  let mut res = String::with_capacity(len);
+
+  // repeating an empty string could take forever for nothing
+  if len > 0 {
+    for i in 0 .. n {
+      if i > 0 { res.push_str(&sep) }
+      res.push_str(&str);
+    }
+  }
 
-  let mut start = expect!(Number, vm)? as usize;
-  let mut end = optional!(Number, len as f64, vm) as usize;
+  Ok(Value::String(res.into()))
+}
 
-  start = start.clamp(0, len);
-  end = end.clamp(0, len);
-  start = start.clamp(0, end);
+/// `len` is `None` if computing it overflowed
+fn check_len(len: Option<usize>) -> Result<usize, RuntimeError> {
+  match len {
+    Some(len) if len <= MAX_STR_LEN => Ok(len),
+    _ => Err("resulting string too large".into())
+  }
+}
 
-  Ok(Value::String(str[start..end].to_string()))
-}
\ No newline at end of file
+fn str_reverse(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let str = expect!(String, vm)?;
+
+  Ok(Value::String(str.chars().rev().collect::<String>().into()))
+}
+
+fn str_starts_with(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let str = expect!(String, vm)?;
+  let pat = expect!(String, vm)?;
+
+  Ok(Value::Bool(str.starts_with(pat.as_str())))
+}
+
+fn str_ends_with(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let str = expect!(String, vm)?;
+  let pat = expect!(String, vm)?;
+
+  Ok(Value::Bool(str.ends_with(pat.as_str())))
+}
+
+fn str_contains(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let str = expect!(String, vm)?;
+  let pat = expect!(String, vm)?;
+
+  Ok(Value::Bool(str.contains(pat.as_str())))
+}
+
+/// returns the padding needed to make `str` `width` code points long
+fn padding(vm: &mut VM) -> Result<(Str, String), RuntimeError> {
+  let str = expect!(String, vm)?;
+  let width = expect!(Integer, vm)?;
+  let fill = optional!(String, " ".into(), vm);
+
+  arg_check!(fill.char_len() == 1, 3, "fill must be a single character")?;
+
+  let n = (width.max(0) as usize).saturating_sub(str.char_len());
+  check_len(fill.len().checked_mul(n).and_then(| len | len.checked_add(str.len())))?;
+
+  Ok((str, fill.repeat(n)))
+}
+
+fn str_pad_left(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let (str, pad) = padding(vm)?;
+
+  Ok(Value::String((pad + &str).into()))
+}
+
+fn str_pad_right(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let (str, pad) = padding(vm)?;
+
+  Ok(Value::String((str.to_string() + &pad).into()))
+}
+
+/// the inverse of `byte`, builds a string from its bytes
+fn str_char(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let narg = vm.nci.top - vm.nci.base;
+  let mut bytes = Vec::new();
+
+  for i in 1 ..= narg {
+    let b = expect!(Integer, vm)?;
+
+    arg_check!(u8::try_from(b).is_ok(), i, "value out of range")?;
+    bytes.push(b as u8);
+  }
+
+  match String::from_utf8(bytes) {
+    Ok(str) => Ok(Value::String(str.into())),
+    Err(..) => Err("invalid UTF-8 sequence".into())
+  }
+}
+
+/// joins an array of strings and numbers, also `array.join`
+pub fn str_join(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let array = expect!(Array, vm)?;
+  let sep = optional!(String, "".into(), vm);
+  let mut parts = Vec::with_capacity(array.len());
+
+  for (i, val) in array.vec.borrow().iter().enumerate() {
+    match val {
+      Value::String(s) => parts.push(s.to_string()),
+      Value::Number(..) | Value::Integer(..) => parts.push(val.to_string()),
+
+      _ => return Err(format!("bad argument #1 (invalid value ({:?}) at index {})", Type::from(val), i).into())
+    }
+  }
+
+  Ok(Value::String(parts.join(&sep).into()))
+}
@@ -0,0 +1,251 @@
+use std::collections::{ HashMap, HashSet };
+use std::rc::Rc;
+
+use crate::common::{ Value, Table, Array };
+use crate::vm::{ VM, env::{ Env, aux::*, jsonlib::is_null }, RuntimeError };
+use crate::{ expect, expect_any };
+
+/// how deep tables and arrays can be nested for `deep_clone` and
+/// `deep_equal`, they recurse once per level
+const MAX_DEPTH: usize = 200;
+
+pub fn load(env: &mut Env) {
+  let tbl = Table::new();
+
+  tbl_builtin(&tbl, "keys", &table_keys);
+  tbl_builtin(&tbl, "values", &table_values);
+  tbl_builtin(&tbl, "entries", &table_entries);
+  tbl_builtin(&tbl, "remove", &table_remove);
+  tbl_builtin(&tbl, "has", &table_has);
+  tbl_builtin(&tbl, "merge", &table_merge);
+  tbl_builtin(&tbl, "clone", &table_clone);
+  tbl_builtin(&tbl, "deep_clone", &table_deep_clone);
+  tbl_builtin(&tbl, "deep_equal", &table_deep_equal);
+
+  env.set_global(Value::String("table".into()), Value::Table(tbl))
+}
+
+fn table_keys(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let tbl = expect!(Table, vm)?;
+  let keys = tbl.tbl.borrow().keys().cloned().collect();
+
+  Ok(Value::Array(Array::new(keys)))
+}
+
+fn table_values(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let tbl = expect!(Table, vm)?;
+  let values = tbl.tbl.borrow().values().cloned().collect();
+
+  Ok(Value::Array(Array::new(values)))
+}
+
+/// an array of `[key, value]` pairs
+fn table_entries(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let tbl = expect!(Table, vm)?;
+
+  let entries = tbl.tbl.borrow().iter()
+    .map(| (k, v) | Value::Array(Array::new(vec![ k.clone(), v.clone() ])))
+    .collect();
+
+  Ok(Value::Array(Array::new(entries)))
+}
+
+/// removes the entry and returns its value, or nil if there was none
+fn table_remove(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let tbl = expect!(Table, vm)?;
+  let key = expect_any!(vm);
+
+  tbl.validate_index(&key)?;
+  let val = tbl.tbl.borrow_mut().remove(&key);
+
+  Ok(val.unwrap_or(Value::Nil))
+}
+
+fn table_has(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let tbl = expect!(Table, vm)?;
+  let key = expect_any!(vm);
+
+  tbl.validate_index(&key)?;
+  let has = tbl.tbl.borrow().contains_key(&key);
+
+  Ok(Value::Bool(has))
+}
+
+/// copies the entries of every other table into the first one and returns
+/// it, later tables win
+fn table_merge(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let tbl = expect!(Table, vm)?;
+  let narg = vm.nci.top - vm.nci.base;
+
+  for _ in 0 .. narg {
+    let other = expect!(Table, vm)?;
+
+    // merging a table into itself changes nothing
+    if Rc::ptr_eq(&tbl.tbl, &other.tbl) { continue }
+
+    let entries = other.tbl.borrow().iter()
+      .map(| (k, v) | (k.clone(), v.clone()))
+      .collect::<Vec<_>>();
+
+    for (k, v) in entries {
+      assign(vm, &tbl, k, v)?;
+    }
+  }
+
+  Ok(Value::Table(tbl))
+}
+
+/// `tbl[key] = val` like in a script, keys the table doesn't have go
+/// through its `__newindex`
+fn assign(vm: &mut VM, tbl: &Table, key: Value, val: Value) -> Result<(), RuntimeError> {
+  let newindex = tbl.get_meta("__newindex");
+
+  if newindex != Value::Nil && tbl.get(&key)? == Value::Nil {
+    vm.call_value(newindex, vec![ Value::Table(tbl.clone()), key, val ])?;
+    return Ok(())
+  }
+
+  tbl.insert(key, val)
+}
+
+/// a shallow copy with the same metatable, `json.null` isn't copied
+fn table_clone(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let tbl = expect!(Table, vm)?;
+
+  if is_null(&Value::Table(tbl.clone())) {
+    return Ok(Value::Table(tbl))
+  }
+
+  let copy = Table::new();
+
+  *copy.tbl.borrow_mut() = tbl.tbl.borrow().clone();
+  copy.set_metatable(tbl.metatable());
+
+  let copy = Value::Table(copy);
+  vm.heap.track(&copy);
+
+  Ok(copy)
+}
+
+/// copies tables and arrays recursively, shared and cyclic references are
+/// kept as they are in the original and `json.null` stays `json.null`
+fn table_deep_clone(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let val = expect_any!(vm);
+  let mut seen = HashMap::new();
+
+  deep_clone(vm, &val, &mut seen, 0)
+}
+
+fn too_deep() -> RuntimeError {
+  "too many nested tables and arrays".into()
+}
+
+/// `seen` maps the containers already copied to their copy
+fn deep_clone(vm: &mut VM, val: &Value, seen: &mut HashMap<usize, Value>, depth: usize) -> Result<Value, RuntimeError> {
+  if depth > MAX_DEPTH && matches!(val, Value::Table(..) | Value::Array(..)) {
+    return Err(too_deep())
+  }
+
+  match val {
+    _ if is_null(val) => Ok(val.clone()),
+
+    Value::Table(t) => {
+      let ptr = Rc::as_ptr(&t.tbl) as usize;
+      if let Some(copy) = seen.get(&ptr) { return Ok(copy.clone()) }
+
+      let copy = Table::new();
+      copy.set_metatable(t.metatable());
+
+      let res = Value::Table(copy.clone());
+      vm.heap.track(&res);
+      seen.insert(ptr, res.clone());
+
+      // the original can't change while it's being copied
+      let entries = t.tbl.borrow().iter()
+        .map(| (k, v) | (k.clone(), v.clone()))
+        .collect::<Vec<_>>();
+
+      for (k, v) in entries {
+        let k = deep_clone(vm, &k, seen, depth + 1)?;
+        let v = deep_clone(vm, &v, seen, depth + 1)?;
+
+        copy.tbl.borrow_mut().insert(k, v);
+      }
+
+      Ok(res)
+    }
+
+    Value::Array(a) => {
+      let ptr = Rc::as_ptr(&a.vec) as usize;
+      if let Some(copy) = seen.get(&ptr) { return Ok(copy.clone()) }
+
+      let copy = Array::with_capacity(a.len());
+
+      let res = Value::Array(copy.clone());
+      vm.heap.track(&res);
+      seen.insert(ptr, res.clone());
+
+      let vals = a.vec.borrow().clone();
+
+      for v in vals {
+        let v = deep_clone(vm, &v, seen, depth + 1)?;
+        copy.vec.borrow_mut().push(v);
+      }
+
+      Ok(res)
+    }
+
+    _ => Ok(val.clone())
+  }
+}
+
+/// compares tables and arrays by their contents, keys are still compared
+/// with `==`
+fn table_deep_equal(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let lhs = expect_any!(vm);
+  let rhs = expect_any!(vm);
+  let mut seen = HashSet::new();
+
+  Ok(Value::Bool(deep_equal(&lhs, &rhs, &mut seen, 0)?))
+}
+
+/// pairs in `seen` are being compared further up, assuming they are equal
+/// stops cycles and they are checked there anyway
+fn deep_equal(lhs: &Value, rhs: &Value, seen: &mut HashSet<(usize, usize)>, depth: usize) -> Result<bool, RuntimeError> {
+  match (lhs, rhs) {
+    (Value::Table(a), Value::Table(b)) => {
+      let pair = (Rc::as_ptr(&a.tbl) as usize, Rc::as_ptr(&b.tbl) as usize);
+      if pair.0 == pair.1 || !seen.insert(pair) { return Ok(true) }
+      if depth > MAX_DEPTH { return Err(too_deep()) }
+
+      let (a, b) = (a.tbl.borrow(), b.tbl.borrow());
+      if a.len() != b.len() { return Ok(false) }
+
+      for (k, v) in a.iter() {
+        match b.get(k) {
+          Some(v2) if deep_equal(v, v2, seen, depth + 1)? => {}
+          _ => return Ok(false)
+        }
+      }
+
+      Ok(true)
+    }
+
+    (Value::Array(a), Value::Array(b)) => {
+      let pair = (Rc::as_ptr(&a.vec) as usize, Rc::as_ptr(&b.vec) as usize);
+      if pair.0 == pair.1 || !seen.insert(pair) { return Ok(true) }
+      if depth > MAX_DEPTH { return Err(too_deep()) }
+
+      let (a, b) = (a.vec.borrow(), b.vec.borrow());
+      if a.len() != b.len() { return Ok(false) }
+
+      for (v, v2) in a.iter().zip(b.iter()) {
+        if !deep_equal(v, v2, seen, depth + 1)? { return Ok(false) }
+      }
+
+      Ok(true)
+    }
+
+    _ => Ok(lhs == rhs)
+  }
+}
@@ -0,0 +1,89 @@
+use std::convert::TryFrom;
+
+use crate::common::{ Value, Table, Array, Type };
+use crate::vm::{ VM, env::{ Env, aux::* }, RuntimeError };
+use crate::{ expect, expect_any, optional, arg_check };
+
+pub fn load(env: &mut Env) {
+  let tbl = Table::new();
+
+  tbl_builtin(&tbl, "chars", &utf8_chars);
+  tbl_builtin(&tbl, "codepoint", &utf8_codepoint);
+  tbl_builtin(&tbl, "char", &utf8_char);
+  tbl_builtin(&tbl, "valid", &utf8_valid);
+  tbl_builtin(&tbl, "decode", &utf8_decode);
+
+  env.set_global(Value::String("utf8".into()), Value::Table(tbl))
+}
+
+/// turns an array of bytes like the one from `string.bytes` into a `Vec<u8>`
+fn to_bytes(val: &Value) -> Result<Vec<u8>, RuntimeError> {
+  if let Value::Array(a) = val {
+    let mut bytes = Vec::with_capacity(a.len());
+
+    for b in a.vec.borrow().iter() {
+      let b = b.to_integer().and_then(| b | u8::try_from(b).ok());
+      bytes.push(b.ok_or("bad argument #1 (expected an array of bytes)")?);
+    }
+
+    Ok(bytes)
+  } else {
+    Err(format!("bad argument #1 (expected string or array got {:?})", Type::from(val)).into())
+  }
+}
+
+fn utf8_chars(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let str = expect!(String, vm)?;
+  let chars = str.chars().map(| c | Value::String(c.to_string().into())).collect();
+
+  Ok(Value::Array(Array::new(chars)))
+}
+
+fn utf8_codepoint(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let str = expect!(String, vm)?;
+  let idx = optional!(Integer, 0, vm);
+
+  let c = usize::try_from(idx).ok().and_then(| idx | str.char_at(idx));
+
+  if let Some(c) = c {
+    Ok(Value::Integer(c as i64))
+  } else {
+    Err("bad argument #2 (out of bounds)".into())
+  }
+}
+
+fn utf8_char(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let narg = vm.nci.top - vm.nci.base;
+  let mut str = String::new();
+
+  for i in 1 ..= narg {
+    let code = expect!(Integer, vm)?;
+    let c = u32::try_from(code).ok().and_then(char::from_u32);
+
+    arg_check!(c.is_some(), i, "value out of range")?;
+    str.push(c.unwrap());
+  }
+
+  Ok(Value::String(str.into()))
+}
+
+/// strings are always valid, arrays of bytes are checked
+fn utf8_valid(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let val = expect_any!(vm);
+
+  match val {
+    Value::String(..) => Ok(Value::Bool(true)),
+    _ => Ok(Value::Bool(std::str::from_utf8(&to_bytes(&val)?).is_ok()))
+  }
+}
+
+/// the inverse of `string.bytes`, returns nil if the bytes aren't valid UTF-8
+fn utf8_decode(vm: &mut VM) -> Result<Value, RuntimeError> {
+  let val = expect_any!(vm);
+  let bytes = to_bytes(&val)?;
+
+  match String::from_utf8(bytes) {
+    Ok(str) => Ok(Value::String(str.into())),
+    Err(..) => Ok(Value::Nil)
+  }
+}
@@ -24,25 +24,30 @@ impl RuntimeError {
 
   fn trace(&self, call_stack: &Vec<CallInfo>) -> String {
     let mut trace = "stack trace:\n".to_string();
+    let mut idx = call_stack.len();
 
-    if let RuntimeError::StackOverflow = self {
-      let top = call_stack.last().unwrap(); // I don't think this should error
-      let overflowed = self.fmt_trace(top);
+    // collapse runs of the same function (recursion) into one frame
+    while idx > 0 {
+      idx -= 1;
 
-      trace += format!("{}\t...\n{}", overflowed, overflowed).as_str();
+      let call = &call_stack[idx];
+      let mut tail_calls = call.tail_calls;
+      let mut repeat = 0;
 
-      for idx in (0 .. call_stack.len()).rev() {
-        let call = &call_stack[idx];
+      while idx > 0 && same_function(&call_stack[idx - 1], call) {
+        idx -= 1;
+        repeat += 1;
+        tail_calls += call_stack[idx].tail_calls;
+      }
 
-        if call.closure != top.closure {
-          trace += self.fmt_trace(call).as_str()
-        }
+      trace += self.fmt_trace(call).as_str();
+
+      if repeat > 0 {
+        trace += format!("\t...\t({} more)\n", repeat).as_str();
       }
-    } else {
-      for idx in (0 .. call_stack.len()).rev() {
-        let call = &call_stack[idx];
 
-        trace += self.fmt_trace(call).as_str()
+      if tail_calls > 0 {
+        trace += format!("\t(...{} tail calls...)\n", tail_calls).as_str();
       }
     }
 
@@ -75,6 +80,13 @@ impl RuntimeError {
   }
 }
 
+fn same_function(a: &CallInfo, b: &CallInfo) -> bool {
+  a.is_builtin == b.is_builtin
+    && a.closure.name == b.closure.name
+    && a.closure.file_name == b.closure.file_name
+    && a.closure.code == b.closure.code
+}
+
 impl From<String> for RuntimeError {
   fn from(str: String) -> Self {
     RuntimeError::CustomError(str)
@@ -0,0 +1,298 @@
+use std::collections::{ HashMap, HashSet };
+use std::mem::{ size_of, take };
+use std::cell::RefCell;
+use std::rc::Weak;
+use std::rc::Rc;
+
+use crate::common::{ Value, Closure, Table };
+
+/// number of tracked objects before the first automatic collection
+const MIN_THRESHOLD: usize = 1024;
+
+type TableObj = RefCell<HashMap<Value, Value>>;
+type ArrayObj = RefCell<Vec<Value>>;
+type UpvalObj = RefCell<Vec<(usize, usize, Value)>>;
+
+enum Object {
+  Table(Weak<TableObj>),
+  Array(Weak<ArrayObj>),
+  Upvals(Weak<UpvalObj>)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapStats {
+  /// live tables the collector knows about
+  pub tables: usize,
+  /// live arrays the collector knows about
+  pub arrays: usize,
+  /// live closures with captured upvalues
+  pub closures: usize,
+  /// rough estimate of the memory used by those objects
+  pub bytes: usize,
+  /// number of collections so far
+  pub collections: usize,
+  /// number of objects freed by all collections so far
+  pub freed: usize
+}
+
+/// Tracks heap objects that can form reference cycles. `Rc` frees everything
+/// else, so a collection only has to find tracked objects that are still alive
+/// but unreachable from the roots and clear them, which breaks their cycles.
+///
+/// Tables with a `__gc` metamethod are kept alive by the heap until they
+/// become unreachable, then they are resurrected for one more cycle so the
+/// VM can run their finalizer.
+pub struct Heap {
+  objects: HashMap<usize, Object>,
+  marked: HashSet<usize>,
+  gray: Vec<Value>,
+  /// weak tables found while marking, with their weak keys and values flags
+  weak: Vec<(Table, bool, bool)>,
+  finalizable: HashMap<usize, Table>,
+  pending: Vec<Table>,
+  threshold: usize,
+  collections: usize,
+  freed: usize
+}
+
+impl Object {
+  fn is_alive(&self) -> bool {
+    match self {
+      Object::Table(t) => t.strong_count() > 0,
+      Object::Array(a) => a.strong_count() > 0,
+      Object::Upvals(u) => u.strong_count() > 0
+    }
+  }
+
+  /// empties the object, returns false if it was already freed
+  fn clear(&self) -> bool {
+    match self {
+      Object::Table(t) => t.upgrade().map(| t | t.borrow_mut().clear()).is_some(),
+      Object::Array(a) => a.upgrade().map(| a | a.borrow_mut().clear()).is_some(),
+      Object::Upvals(u) => u.upgrade().map(| u | u.borrow_mut().clear()).is_some()
+    }
+  }
+}
+
+impl Default for Heap {
+  fn default() -> Self {
+    Heap::new()
+  }
+}
+
+impl Heap {
+  pub fn new() -> Self {
+    Heap {
+      objects: HashMap::new(),
+      marked: HashSet::new(),
+      gray: Vec::new(),
+      weak: Vec::new(),
+      finalizable: HashMap::new(),
+      pending: Vec::new(),
+      threshold: MIN_THRESHOLD,
+      collections: 0,
+      freed: 0
+    }
+  }
+
+  /// starts tracking `val` if it is an object that can be part of a cycle
+  pub fn track(&mut self, val: &Value) {
+    match val {
+      Value::Table(t) => {
+        self.objects.insert(Rc::as_ptr(&t.tbl) as usize, Object::Table(Rc::downgrade(&t.tbl)));
+      }
+
+      Value::Array(a) => {
+        self.objects.insert(Rc::as_ptr(&a.vec) as usize, Object::Array(Rc::downgrade(&a.vec)));
+      }
+
+      Value::Closure(c) => self.track_upvals(&c.upvals),
+
+      _ => {}
+    }
+  }
+
+  fn track_upvals(&mut self, upvals: &Rc<UpvalObj>) {
+    if !upvals.borrow().is_empty() {
+      self.objects.insert(Rc::as_ptr(upvals) as usize, Object::Upvals(Rc::downgrade(upvals)));
+    }
+  }
+
+  /// keeps `tbl` alive until it's unreachable so its `__gc` can be called
+  pub fn set_finalizer(&mut self, tbl: &Table) {
+    self.finalizable.insert(Rc::as_ptr(&tbl.tbl) as usize, tbl.clone());
+  }
+
+  /// schedules every finalizer to be run, used when the VM is dropped
+  pub fn finalize_all(&mut self) {
+    self.pending.extend(self.finalizable.drain().map(| (_, tbl) | tbl));
+  }
+
+  /// tables whose finalizer should be run after the last sweep
+  pub fn take_pending(&mut self) -> Vec<Table> {
+    take(&mut self.pending)
+  }
+
+  #[inline]
+  pub fn should_collect(&self) -> bool {
+    self.objects.len() >= self.threshold
+  }
+
+  /// marks `val` and everything reachable from it as a root
+  pub fn mark(&mut self, val: &Value) {
+    self.gray.push(val.clone());
+    self.propagate();
+  }
+
+  pub fn mark_closure(&mut self, closure: &Closure) {
+    if self.visit_closure(closure) {
+      self.propagate();
+    }
+  }
+
+  /// clears every tracked object that wasn't marked and returns how many
+  /// were freed
+  pub fn sweep(&mut self) -> usize {
+    let unreachable = self.finalizable.keys()
+      .filter(| ptr | !self.marked.contains(ptr))
+      .cloned()
+      .collect::<Vec<usize>>();
+
+    for ptr in unreachable {
+      let tbl = self.finalizable.remove(&ptr).unwrap();
+
+      self.mark(&Value::Table(tbl.clone()));
+      self.pending.push(tbl);
+    }
+
+    for (tbl, weak_keys, weak_values) in take(&mut self.weak) {
+      tbl.tbl.borrow_mut().retain(| k, v | {
+        let dead = (weak_keys && self.is_unmarked(k)) || (weak_values && self.is_unmarked(v));
+        !dead
+      });
+    }
+
+    let objects = take(&mut self.objects);
+    let mut garbage = Vec::new();
+
+    for (ptr, obj) in objects {
+      if !obj.is_alive() { continue }
+
+      if self.marked.contains(&ptr) {
+        self.objects.insert(ptr, obj);
+      } else {
+        garbage.push(obj);
+      }
+    }
+
+    self.marked.clear();
+
+    let mut freed = 0;
+
+    for obj in garbage {
+      if obj.clear() { freed += 1 }
+    }
+
+    // clearing the garbage can free objects that were still marked as alive
+    self.objects.retain(| _, obj | obj.is_alive());
+
+    self.threshold = (self.objects.len() * 2).max(MIN_THRESHOLD);
+    self.collections += 1;
+    self.freed += freed;
+
+    freed
+  }
+
+  pub fn stats(&self) -> HeapStats {
+    let mut stats = HeapStats {
+      collections: self.collections,
+      freed: self.freed,
+      ..HeapStats::default()
+    };
+
+    for obj in self.objects.values() {
+      match obj {
+        Object::Table(t) => if let Some(t) = t.upgrade() {
+          stats.tables += 1;
+          stats.bytes += size_of::<TableObj>() + t.borrow().capacity() * size_of::<(Value, Value)>();
+        },
+
+        Object::Array(a) => if let Some(a) = a.upgrade() {
+          stats.arrays += 1;
+          stats.bytes += size_of::<ArrayObj>() + a.borrow().capacity() * size_of::<Value>();
+        },
+
+        Object::Upvals(u) => if let Some(u) = u.upgrade() {
+          stats.closures += 1;
+          stats.bytes += size_of::<UpvalObj>() + u.borrow().capacity() * size_of::<(usize, usize, Value)>();
+        }
+      }
+    }
+
+    stats
+  }
+
+  fn propagate(&mut self) {
+    while let Some(val) = self.gray.pop() {
+      match &val {
+        Value::Table(t) if self.marked.insert(Rc::as_ptr(&t.tbl) as usize) => {
+          let (weak_keys, weak_values) = t.weak_mode();
+
+          if weak_keys || weak_values {
+            self.weak.push((t.clone(), weak_keys, weak_values));
+          }
+
+          self.track(&val);
+
+          for (k, v) in t.tbl.borrow().iter() {
+            if !weak_keys { self.gray.push(k.clone()) }
+            if !weak_values { self.gray.push(v.clone()) }
+          }
+
+          if let Some(meta) = t.metatable() {
+            self.gray.push(Value::Table(meta));
+          }
+        }
+
+        Value::Array(a) if self.marked.insert(Rc::as_ptr(&a.vec) as usize) => {
+          self.track(&val);
+          self.gray.extend(a.vec.borrow().iter().cloned());
+        }
+
+        Value::Closure(c) => {
+          self.visit_closure(c);
+        }
+
+        _ => {}
+      }
+    }
+  }
+
+  /// returns true if `val` is an object that wasn't reached while marking
+  fn is_unmarked(&self, val: &Value) -> bool {
+    let ptr = match val {
+      Value::Table(t) => Rc::as_ptr(&t.tbl) as usize,
+      Value::Array(a) => Rc::as_ptr(&a.vec) as usize,
+      Value::Closure(c) => Rc::as_ptr(&c.upvals) as usize,
+
+      _ => return false
+    };
+
+    !self.marked.contains(&ptr)
+  }
+
+  /// returns true if the closure had anything left to mark
+  fn visit_closure(&mut self, closure: &Closure) -> bool {
+    if !self.marked.insert(Rc::as_ptr(&closure.upvals) as usize) {
+      return false
+    }
+
+    self.track_upvals(&closure.upvals);
+
+    for (_, _, val) in closure.upvals.borrow().iter() {
+      self.gray.push(val.clone());
+    }
+
+    true
+  }
+}
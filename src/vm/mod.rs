@@ -1,24 +1,42 @@
+use std::convert::TryFrom;
+use std::cmp::Ordering;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{ AtomicUsize, Ordering as AtomicOrdering };
+
 use crate::common::{ Closure, Value, Opcode, Type, Array, Table };
 use crate::vm::env::Env;
+use gc::{ Heap, HeapStats };
 use code::*;
 
 pub mod code;
 pub mod env;
 pub mod error;
+pub mod gc;
 
 pub use code::pretty_print_closure;
 pub use error::RuntimeError;
 
+/// default limit of nested calls before a stack overflow is raised
+pub const MAX_DEPTH: usize = 20000;
+
+/// gives every frame its own id, so upvalues know if the frame that made
+/// their closure is still running
+static NEXT_FRAME: AtomicUsize = AtomicUsize::new(0);
+
 pub struct NativeCallInfo {
   base: usize,
-  top: usize
+  top: usize,
+  /// the first argument, `base` moves past the ones that were read
+  start: usize
 }
 
 impl NativeCallInfo {
   pub fn new() -> Self {
     NativeCallInfo {
       base: 0,
-      top: 0
+      top: 0,
+      start: 0
     }
   }
 }
@@ -27,17 +45,36 @@ impl NativeCallInfo {
 pub struct CallInfo {
   closure: Closure,
   is_builtin: bool,
+  /// called by `VM::call_value`, returns to rust instead of a caller frame
+  from_native: bool,
+  tail_calls: usize,
+  id: usize,
   base: usize,
   pc: usize
 }
 
 impl CallInfo {
   pub fn new(closure: Closure, base: usize) -> Self {
+    let mut pc = 0;
+
+    // skip upvalue captures, they are run by `Opcode::Closure`
+    for i in &closure.code {
+      let i = *i;
+      if get_op(i) == Opcode::GetUpVal && get_c(i) == 1 {
+        pc += 1;
+      } else {
+        break
+      }
+    }
+
     CallInfo {
       closure,
       is_builtin: false,
+      from_native: false,
+      tail_calls: 0,
+      id: NEXT_FRAME.fetch_add(1, AtomicOrdering::Relaxed),
       base,
-      pc: 0
+      pc
     }
   }
 }
@@ -47,7 +84,11 @@ pub struct VM {
   call_stack: Vec<CallInfo>,
   nci: NativeCallInfo,
   regs: Vec<Value>,
-  ncalls: usize
+  heap: Heap,
+  ncallbacks: usize,
+  /// a collection was asked for while a callback was running
+  gc_deferred: bool,
+  max_depth: usize
 }
 
 impl VM {
@@ -57,24 +98,128 @@ impl VM {
       env: Env::new(),
       nci: NativeCallInfo::new(),
       regs: Vec::with_capacity(20),
-      ncalls: 0
+      heap: Heap::new(),
+      ncallbacks: 0,
+      gc_deferred: false,
+      max_depth: MAX_DEPTH
     }
   }
 
-  pub fn run(&mut self) -> Result<(), String> {
-    self.env.load();
+  pub fn set_max_depth(&mut self, depth: usize) {
+    self.max_depth = depth;
+  }
 
-    while self.is_end_of_code() {
-      self.run_once()?;
+  /// Frees tables, arrays and closures that are only kept alive by reference
+  /// cycles and returns how many objects were freed. Values held outside of
+  /// the VM (e.g. by the embedder) are not roots and get cleared if the
+  /// script can't reach them anymore.
+  ///
+  /// Unreachable tables with a `__gc` metamethod are finalized instead and
+  /// freed by the next collection.
+  ///
+  /// While a native function is running a callback the values it holds
+  /// aren't roots, so nothing is freed and 0 is returned, the collection
+  /// runs once the callbacks are done.
+  pub fn collect_garbage(&mut self) -> Result<usize, RuntimeError> {
+    if self.ncallbacks > 0 {
+      self.gc_deferred = true;
+      return Ok(0)
+    }
+
+    self.gc_deferred = false;
+
+    for val in &self.regs {
+      self.heap.mark(val);
+    }
+
+    for call in &self.call_stack {
+      self.heap.mark_closure(&call.closure);
+    }
+
+    self.heap.mark(&Value::Table(self.env.globals.clone()));
+
+    let freed = self.heap.sweep();
+
+    self.run_finalizers()?;
+    Ok(freed)
+  }
+
+  /// runs every `__gc` metamethod like dropping the VM does, for natives
+  /// that end the process without dropping it
+  pub fn finalize_all(&mut self) -> Result<(), RuntimeError> {
+    self.heap.finalize_all();
+    self.run_finalizers()
+  }
+
+  fn run_finalizers(&mut self) -> Result<(), RuntimeError> {
+    for tbl in self.heap.take_pending() {
+      let gc = tbl.get_meta("__gc");
+
+      if gc != Value::Nil {
+        self.call_value(gc, vec![ Value::Table(tbl) ])?;
+      }
     }
 
     Ok(())
   }
 
-  pub fn run_closure(&mut self, closure: Closure) -> Result<(), String> {
-    let base = self.call_stack.last().unwrap().base;
+  /// Calls `func` with `args` and returns its result, this can be used by
+  /// native functions to call back into the script.
+  pub fn call_value(&mut self, func: Value, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let top = self.regs.len();
+    let narg = args.len();
+
+    self.regs.push(func.clone());
+    self.regs.extend(args);
+
+    self.ncallbacks += 1;
+
+    let res = match func {
+      Value::NativeFunc(nf) => {
+        let nci = std::mem::replace(&mut self.nci, NativeCallInfo {
+          base: top + 1,
+          top: top + 1 + narg,
+          start: top + 1
+        });
+
+        let res = (nf.func)(self);
+
+        self.nci = nci;
+        res
+      }
+
+      Value::Closure(c) => {
+        let depth = self.call_stack.len();
+        let mut call = CallInfo::new(c, top + 1);
+        call.from_native = true;
+
+        let mut res = self.push_call(call);
+
+        while res.is_ok() && self.call_stack.len() > depth {
+          res = self.exec();
+        }
+
+        res.map(| _ | self.regs[top].clone())
+      }
+
+      _ => Err(RuntimeError::TypeError("call".into(), Type::from(&func), None))
+    };
+
+    self.ncallbacks -= 1;
+
+    if res.is_ok() {
+      self.regs.truncate(top);
+    }
+
+    res
+  }
+
+  pub fn heap_stats(&self) -> HeapStats {
+    self.heap.stats()
+  }
 
-    self.call_stack.push(CallInfo::new(closure, base));
+  pub fn run(&mut self) -> Result<(), String> {
+    self.env.load();
 
     while self.is_end_of_code() {
       self.run_once()?;
@@ -107,18 +252,13 @@ impl VM {
     macro_rules! get_mut {
       ($pos:expr) => {{
         let pos = $pos;
-        let v = self.regs.get_mut(pos);
 
-        if v.is_none() {
-          while self.regs.get(pos).is_none() {
-            self.regs.push(Value::Nil)
-          }
+        if pos >= self.regs.len() {
+          self.regs.resize(pos + 1, Value::Nil);
+        }
 
-          self.regs.get_mut(pos).unwrap()
-        } else {
-          v.unwrap()
-        }}
-      };
+        &mut self.regs[pos]
+      }};
     }
 
     macro_rules! konst {
@@ -201,25 +341,59 @@ impl VM {
       ($op:tt) => {{
         let rca = RCA!();
         let rcb = RCB!();
-        let t1 = Type::from(rca);
-        let t2 = Type::from(rcb);
 
-        let res = (rca.clone() $op rcb.clone());
-
-        if let Err(()) = res {
-          return Err(RuntimeError::TypeError("perform an arithmetic on".into(), t1, Some(t2)))
+        let res = if let (Value::Number(lhs), Value::Number(rhs)) = (rca, rcb) {
+          Value::Number(lhs $op rhs)
         } else {
-          *RC_mut!() = res.unwrap()
-        }
+          (rca $op rcb)?
+        };
+
+        *RC_mut!() = res
       }};
-    }
 
-    macro_rules! cmp {
-      ($op:tt) => {{
+      // integers wrap around on overflow
+      ($op:tt, $int_op:ident) => {{
         let rca = RCA!();
         let rcb = RCB!();
 
-        *RC_mut!() = Value::Bool(rca $op rcb)
+        let res = match (rca, rcb) {
+          (Value::Integer(lhs), Value::Integer(rhs)) => Value::Integer(lhs.$int_op(*rhs)),
+          (Value::Number(lhs), Value::Number(rhs)) => Value::Number(lhs $op rhs),
+          _ => (rca $op rcb)?
+        };
+
+        *RC_mut!() = res
+      }};
+    }
+
+    macro_rules! bitwise {
+      ($op:tt) => {{
+        let res = (RCA!() $op RCB!())?;
+        *RC_mut!() = res
+      }};
+    }
+
+    macro_rules! eq {
+      ($op:tt) => {{
+        let res = RCA!() $op RCB!();
+        *RC_mut!() = Value::Bool(res)
+      }};
+    }
+
+    // `>` and `>=` are `<` and `<=` with the operands swapped like in Lua
+    macro_rules! cmp {
+      ($op:tt, $swap:expr, $or_eq:expr) => {{
+        let res = match (RCA!(), RCB!()) {
+          (Value::Integer(lhs), Value::Integer(rhs)) => lhs $op rhs,
+          (Value::Number(lhs), Value::Number(rhs)) => lhs $op rhs,
+
+          (lhs, rhs) => {
+            let (lhs, rhs) = if $swap { (rhs.clone(), lhs.clone()) } else { (lhs.clone(), rhs.clone()) };
+            self.less_than(lhs, rhs, $or_eq)?
+          }
+        };
+
+        *RC_mut!() = Value::Bool(res)
       }};
     }
 
@@ -242,7 +416,7 @@ impl VM {
 
       Opcode::SetUpVal => {
         let mut upvals = call.closure.upvals.borrow_mut();
-        let (_, upval) = upvals.get_mut(get_a(i) as usize).unwrap();
+        let (_, _, upval) = upvals.get_mut(get_a(i) as usize).unwrap();
         let val = RB!().clone();
 
         *upval = val
@@ -251,7 +425,7 @@ impl VM {
       Opcode::GetUpVal => {
         let val = {
           let upvals = call.closure.upvals.borrow();
-          let (_, upval) = upvals
+          let (_, _, upval) = upvals
             .get(get_b(i) as usize)
             .unwrap();
 
@@ -288,20 +462,44 @@ impl VM {
           i += 2;
         }
 
-        *RA_mut!() = Value::Table(tbl);
+        let tbl = Value::Table(tbl);
+
+        self.heap.track(&tbl);
+        *RA_mut!() = tbl;
+        self.check_gc()?;
       }
 
       Opcode::NewArray => {
-        let (a, b) = (get_a(i), get_b(i));
+        let (a, b) = (A!(), B!());
         let size = b - a;
 
-        let mut array = Vec::with_capacity(size as usize);
+        let mut array = Vec::with_capacity(size);
 
         for i in a .. b {
-          array.push(self.regs[i as usize].clone())
+          array.push(self.regs[i].clone())
         }
 
         *RA_mut!() = self.new_array(array);
+        self.check_gc()?;
+      }
+
+      Opcode::Concat => {
+        let parts = &self.regs[A!() .. B!()];
+        let len = parts.iter()
+          .map(| v | if let Value::String(s) = v { s.len() } else { 8 })
+          .sum();
+
+        // build the string in one allocation
+        let mut str = String::with_capacity(len);
+
+        for part in parts {
+          match part {
+            Value::String(s) => str.push_str(s),
+            _ => str.push_str(&part.to_string())
+          }
+        }
+
+        *RA_mut!() = Value::String(str.into())
       }
 
       Opcode::GetObj => {
@@ -318,13 +516,13 @@ impl VM {
           }
 
           Value::String(str) => {
-            if let Value::Number(n) = b {
-              let mut n = *n as usize;
-              n = if n == 0 { 1 } else { n };
+            // strings are indexed by code point from 0 like arrays
+            if let Some(n) = b.to_integer() {
+              let c = usize::try_from(n).ok().and_then(| n | str.char_at(n));
 
-              *RA_mut!() = Value::String(str.as_str().get(n - 1 .. n).unwrap_or_default().to_string())
+              *RA_mut!() = c.map(| c | Value::String(c.to_string().into())).unwrap_or(Value::Nil)
             } else {
-              return Err(RuntimeError::TypeError("index a string".into(), Type::from(b), None))
+              return Err(RuntimeError::TypeError("index a string with".into(), Type::from(b), None))
             }
           }
 
@@ -343,37 +541,81 @@ impl VM {
           }
 
           Value::Table(tbl) => {
+            let newindex = tbl.get_meta("__newindex");
+
+            // like in Lua it's only used for keys the table doesn't have
+            if newindex != Value::Nil && tbl.get(idx)? == Value::Nil {
+              let args = vec![ obj.clone(), idx.clone(), val.clone() ];
+
+              self.call_value(newindex, args)?;
+              *self.pc_mut() += 1;
+
+              return Ok(())
+            }
+
             tbl.insert(idx.clone(), val.clone())?;
           }
 
           _ => return Err(self.index_error(&obj))
         }
+
+        // storing an object into another one is how cycles are made
+        if matches!(val, Value::Table(..) | Value::Array(..) | Value::Closure(..)) {
+          self.heap.track(obj);
+        }
       }
 
-      Opcode::Add => arith!(+),
-      Opcode::Sub => arith!(-),
-      Opcode::Mul => arith!(*),
+      Opcode::Add => arith!(+, wrapping_add),
+      Opcode::Sub => arith!(-, wrapping_sub),
+      Opcode::Mul => arith!(*, wrapping_mul),
       Opcode::Div => arith!(/),
-      Opcode::Mod => arith!(%),
 
-      Opcode::Neq => cmp!(!=),
-      Opcode::Eq => cmp!(==),
-      Opcode::Lt => cmp!(>),
-      Opcode::Gt => cmp!(<),
-      Opcode::Le => cmp!(>=),
-      Opcode::Ge => cmp!(<=),
+      // `%` follows the sign of the divisor, the float `%` of rust doesn't
+      Opcode::Mod => {
+        let res = (RCA!() % RCB!())?;
+        *RC_mut!() = res
+      }
+
+      Opcode::BAnd => bitwise!(&),
+      Opcode::BOr => bitwise!(|),
+      Opcode::BXor => bitwise!(^),
+      Opcode::Shl => bitwise!(<<),
+      Opcode::Shr => bitwise!(>>),
+
+      Opcode::IDiv => {
+        let res = RCA!().idiv(RCB!())?;
+        *RC_mut!() = res
+      }
+
+      Opcode::Pow => {
+        let res = RCA!().pow(RCB!())?;
+        *RC_mut!() = res
+      }
+
+      Opcode::Neq => eq!(!=),
+      Opcode::Eq => eq!(==),
+      Opcode::Lt => cmp!(>, true, false),
+      Opcode::Gt => cmp!(<, false, false),
+      Opcode::Le => cmp!(>=, true, true),
+      Opcode::Ge => cmp!(<=, false, true),
 
       Opcode::Neg => {
         let rcb = RCB!();
-        if let Value::Number(n) = rcb {
-          *RA_mut!() = Value::Number(-n)
-        } else {
-          return Err(RuntimeError::TypeError("perform an arithmetic on".into(), Type::from(rcb), None))
+        match rcb {
+          Value::Number(n) => *RA_mut!() = Value::Number(-n),
+          Value::Integer(n) => *RA_mut!() = Value::Integer(n.wrapping_neg()),
+
+          _ => return Err(RuntimeError::TypeError("perform an arithmetic on".into(), Type::from(rcb), None))
         }
       }
 
       Opcode::Not => {
-        *RA_mut!() = Value::Bool(!self.bool(RCB!()))
+        *RA_mut!() = Value::Bool(!RCB!().truthy())
+      }
+
+      Opcode::BNot => {
+        let res = RCB!().bnot()?;
+        *RA_mut!() = res
       }
 
       Opcode::Jmp => {
@@ -387,13 +629,15 @@ impl VM {
       }
 
       Opcode::Test => {
-        if self.bool(RA!()) == (get_b(i) == 0) {
+        if RA!().truthy() == (get_b(i) == 0) {
           *self.pc_mut() += 2;
           return Ok(())
         }
       }
 
-      Opcode::Call => {
+      Opcode::Call | Opcode::TailCall => {
+        // the main chunk has no frame to reuse
+        let tail = get_op(i) == Opcode::TailCall && base > 0;
         let func = RA!().clone();
         let base = A!() + 1;
         let b = B!();
@@ -402,9 +646,11 @@ impl VM {
           Value::NativeFunc(nf) => {
             self.nci = NativeCallInfo {
               base,
-              top: b
+              top: b,
+              start: base
             };
 
+            let depth = self.call_stack.len();
             let ret = (nf.func)(self);
 
             if let Err(e) = ret {
@@ -414,7 +660,8 @@ impl VM {
               let mut info = CallInfo::new(c, 0); // for trace
               info.is_builtin = true;
 
-              self.call_stack.push(info);
+              // below the frames of any callback that failed inside it
+              self.call_stack.insert(depth, info);
 
               return Err(e)
             } else {
@@ -432,22 +679,10 @@ impl VM {
               }
             }
 
-            let mut call = CallInfo::new(c.clone(), base);
-
-            for i in &call.closure.code {
-              let i = *i;
-              if get_op(i) == Opcode::GetUpVal && get_c(i) == 1 {
-                call.pc += 1;
-              } else {
-                break
-              }
-            }
-
-            self.call_stack.push(call);
-            self.ncalls += 1;
-
-            if self.ncalls >= 20000 {
-              return Err(RuntimeError::StackOverflow)
+            if tail {
+              self.tail_call(c, A!(), b);
+            } else {
+              self.push_call(CallInfo::new(c, base))?;
             }
 
             return Ok(()) // don't skip first instruction of new function
@@ -464,15 +699,32 @@ impl VM {
           panic!("this is impossible!")
         };
 
-        let mut upvals = cl.upvals.borrow_mut();
+        let a = get_a(i);
+
+        // every closure gets its own upvalues, the constant is only the
+        // prototype shared by all of them
+        let mut new = cl.clone();
+        new.upvals = Rc::new(RefCell::new(Vec::new()));
+
+        let mut upvals = new.upvals.borrow_mut();
 
         for i in cl.code.iter() {
           let i = *i;
           if get_op(i) == Opcode::GetUpVal && get_c(i) == 1 {
             let b = get_b(i);
-            let val = self.regs[b as usize].clone();
 
-            upvals.insert(get_a(i) as usize, (b, val));
+            // the register is kept with the value so `Return` writes it
+            // back to the same place
+            let pos = base + b as usize;
+
+            // a function that refers to itself captures the new closure
+            let val = if b == a {
+              Value::Closure(new.clone())
+            } else {
+              self.regs.get(pos).unwrap_or(&Value::Nil).clone()
+            };
+
+            upvals.push((call.id, pos, val));
           } else {
             break
           }
@@ -480,7 +732,11 @@ impl VM {
 
         drop(upvals);
 
-        *RA_mut!() = Value::Closure(cl.clone());
+        let cl = Value::Closure(new);
+
+        self.heap.track(&cl);
+        *RA_mut!() = cl;
+        self.check_gc()?;
       }
 
       Opcode::Return => {
@@ -491,16 +747,22 @@ impl VM {
         };
 
         let base = if base == 0 { base } else { base - 1 };
-        self.regs[base as usize] = v;
+
+        // the main chunk can return before it used any register
+        if let Some(reg) = self.regs.get_mut(base) {
+          *reg = v;
+        }
 
         let call = self.call_stack.pop().unwrap();
+        self.write_upvals(&call.closure);
 
-        for (pos, upval) in &*call.closure.upvals.borrow() {
-          let val = upval.clone();
-          self.regs[*pos as usize] = val;
+        // the registers of the frame are dead now, the collector marks every
+        // register so they would keep what they hold alive
+        if !self.call_stack.is_empty() {
+          self.regs.truncate(base + 1);
         }
 
-        if self.is_end_of_code() {
+        if !call.from_native && self.is_end_of_code() {
           *self.pc_mut() += 1;
         }
 
@@ -519,17 +781,88 @@ impl VM {
     Ok(())
   }
 
+  fn push_call(&mut self, call: CallInfo) -> Result<(), RuntimeError> {
+    self.call_stack.push(call);
+
+    if self.call_stack.len() > self.max_depth {
+      return Err(RuntimeError::StackOverflow)
+    }
+
+    Ok(())
+  }
+
+  /// replaces the current frame with a call to `closure`, the function and
+  /// its arguments are in `Reg[a] .. Reg[b]`
+  fn tail_call(&mut self, closure: Closure, a: usize, b: usize) {
+    let call = self.call_stack.pop().unwrap();
+    let args = self.regs[a .. b].to_vec();
+
+    self.write_upvals(&call.closure);
+
+    let func = call.base - 1;
+    let narg = args.len() - 1;
+    let top = call.base + closure.nparams as usize;
+
+    for (i, val) in args.into_iter().enumerate() {
+      self.regs[func + i] = val;
+    }
+
+    while self.regs.len() < top {
+      self.regs.push(Value::Nil);
+    }
+
+    for i in call.base + narg .. top {
+      self.regs[i] = Value::Nil;
+    }
+
+    let mut next = CallInfo::new(closure, call.base);
+    next.from_native = call.from_native;
+    next.tail_calls = call.tail_calls + 1;
+
+    self.call_stack.push(next);
+  }
+
+  /// writes the upvalues of a closure that returned back to the registers
+  /// they came from, unless the frame that made it has returned already
+  fn write_upvals(&mut self, closure: &Closure) {
+    for (frame, pos, upval) in &*closure.upvals.borrow() {
+      // ids grow up the stack
+      if self.call_stack.binary_search_by_key(frame, | c | c.id).is_ok() {
+        self.regs[*pos] = upval.clone();
+      }
+    }
+  }
+
   fn error(&self, err: String) -> String {
     let call = self.call();
 
     format!("{}:{}: {}", call.closure.file_name, call.closure.lines[call.pc], err)
   }
 
-  fn bool(&self, val: &Value) -> bool {
-    match val {
-      Value::Bool(b) => *b,
-      Value::Nil => false,
-      _ => true
+  /// `lhs < rhs`, or `lhs <= rhs` if `or_eq`, for numbers and strings, else
+  /// calls the `__lt` or `__le` metamethod of either operand
+  fn less_than(&mut self, lhs: Value, rhs: Value, or_eq: bool) -> Result<bool, RuntimeError> {
+    match (&lhs, &rhs) {
+      (Value::Integer(..) | Value::Number(..), Value::Integer(..) | Value::Number(..))
+        | (Value::String(..), Value::String(..)) => {
+        let ord = lhs.partial_cmp(&rhs);
+        Ok(ord == Some(Ordering::Less) || (or_eq && ord == Some(Ordering::Equal)))
+      }
+
+      _ => {
+        let event = if or_eq { "__le" } else { "__lt" };
+
+        let meta = [&lhs, &rhs].iter().find_map(| val | match val {
+          Value::Table(t) => Some(t.get_meta(event)).filter(| m | *m != Value::Nil),
+          _ => None
+        });
+
+        if let Some(meta) = meta {
+          Ok(self.call_value(meta, vec![ lhs, rhs ])?.truthy())
+        } else {
+          Err(RuntimeError::TypeError("compare".into(), Type::from(&lhs), Some(Type::from(&rhs))))
+        }
+      }
     }
   }
 
@@ -539,8 +872,21 @@ impl VM {
   }
 
   #[inline]
-  fn new_array(&self, vals: Vec<Value>) -> Value {
-    Value::Array(Array::new(vals))
+  fn new_array(&mut self, vals: Vec<Value>) -> Value {
+    let array = Value::Array(Array::new(vals));
+
+    self.heap.track(&array);
+    array
+  }
+
+  #[inline]
+  fn check_gc(&mut self) -> Result<(), RuntimeError> {
+    // natives running a callback may hold values that aren't rooted
+    if self.ncallbacks == 0 && (self.gc_deferred || self.heap.should_collect()) {
+      self.collect_garbage()?;
+    }
+
+    Ok(())
   }
 
   #[inline]
@@ -567,4 +913,17 @@ impl VM {
   fn pc_mut(&mut self) -> &mut usize {
     &mut self.call_mut().pc
   }
+}
+
+impl Drop for VM {
+  fn drop(&mut self) {
+    // errors can't be reported from here
+    self.finalize_all().ok();
+
+    // nothing is reachable anymore, including `_G` which holds itself
+    self.regs.clear();
+    self.call_stack.clear();
+    self.heap.track(&Value::Table(self.env.globals.clone()));
+    self.heap.sweep();
+  }
 }
\ No newline at end of file
@@ -0,0 +1,221 @@
+//! runs the scripts in `tests/scripts`, `name.mn` has to print what's in
+//! `name.out`
+
+use std::process::{ Command, Output };
+use std::path::Path;
+use std::fs;
+use std::env;
+
+fn moon(file: &str, dir: &Path) -> Output {
+  Command::new(env!("CARGO_BIN_EXE_moon"))
+    .arg(file)
+    .current_dir(dir)
+    .output()
+    .expect("could not run moon")
+}
+
+fn run(name: &str) {
+  let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scripts");
+  let out = moon(&format!("{}.mn", name), &dir);
+
+  assert!(out.status.success(), "{}.mn failed:\n{}", name, String::from_utf8_lossy(&out.stderr));
+
+  let expected = fs::read_to_string(dir.join(format!("{}.out", name))).unwrap();
+  assert_eq!(String::from_utf8_lossy(&out.stdout), expected);
+}
+
+/// runs `src` from a file in the temporary directory, returns the file name
+/// and the output
+fn script(name: &str, src: &str) -> (String, Output) {
+  let dir = env::temp_dir();
+  let file = format!("moon-{}-{}.mn", name, std::process::id());
+  fs::write(dir.join(&file), src).unwrap();
+  let out = moon(&file, &dir);
+  fs::remove_file(dir.join(&file)).ok();
+
+  (file, out)
+}
+
+/// runs `src` and checks that it stops with `err`
+fn fails(name: &str, src: &str, err: &str) {
+  let (file, out) = script(name, src);
+
+  assert!(!out.status.success(), "{} did not fail", name);
+  let stderr = String::from_utf8_lossy(&out.stderr);
+  let expected = format!("{}:1: {}", file, err);
+  assert!(stderr.starts_with(&expected), "expected `{}`, got:\n{}", expected, stderr);
+}
+
+/// runs `src` and checks its exit code and what it printed
+fn exits(name: &str, src: &str, code: i32, stdout: &str) {
+  let (_, out) = script(name, src);
+
+  assert_eq!(out.status.code(), Some(code), "{}:\n{}", name, String::from_utf8_lossy(&out.stderr));
+  assert_eq!(String::from_utf8_lossy(&out.stdout), stdout);
+}
+
+#[test]
+fn upvalues() {
+  run("upvalues")
+}
+
+#[test]
+fn gc() {
+  run("gc")
+}
+
+#[test]
+fn weak_tables() {
+  run("weak_tables")
+}
+
+#[test]
+fn numbers() {
+  run("numbers")
+}
+
+#[test]
+fn malformed_numbers() {
+  fails("hex", "print(0x)", "malformed number (missing hex digits) near '0x'");
+  fails("exp", "print(1e+)", "malformed number (missing exponent digits) near '1e+'");
+  fails("bin", "print(0b102)", "malformed number (invalid binary digit '2') near '0b102'");
+  fails("oct", "print(0o9)", "malformed number (invalid octal digit '9') near '0o9'");
+  fails("dec", "print(12abc)", "malformed number (invalid digit 'a') near '12abc'");
+}
+
+#[test]
+fn escapes() {
+  run("escapes")
+}
+
+#[test]
+fn bad_escapes() {
+  fails("esc", r#"print("\q")"#, "invalid escape");
+  fails("hex", r#"print("\x4")"#, "hexadecimal digit expected");
+  fails("utf8", r#"print("\xff")"#, r"invalid UTF-8 in \x escapes, use \u{xx} for a code point");
+  fails("point", r#"print("\u{110000}")"#, "invalid unicode code point");
+  fails("brace", r#"print("\u{48")"#, r"missing '}' in \u{xxxx}");
+  fails("line", "print(\"abc\n\")", "unfinished string near '\"abc'");
+}
+
+#[test]
+fn format() {
+  run("format")
+}
+
+#[test]
+fn bad_formats() {
+  fails("float", r#"string.format("%d", 1.5)"#, "bad argument #2 (number has no integer representation)");
+  fails("string", r#"string.format("%d", "x")"#, "bad argument #2 (expected number got string)");
+  fails("missing", r#"string.format("%s %s", 1)"#, "bad argument #3 (no value)");
+  fails("conv", r#"string.format("%y", 1)"#, "bad argument #1 (invalid conversion '%y' to format)");
+  fails("width", r#"string.format("%123d", 1)"#, "bad argument #1 (invalid conversion (width or precision too long))");
+  fails("literal", r#"string.format("%q", [1])"#, "bad argument #2 (value has no literal form)");
+}
+
+#[test]
+fn patterns() {
+  run("patterns")
+}
+
+#[test]
+fn bad_patterns() {
+  fails("set", r#"string.match("a", "[a")"#, "bad argument #2 (malformed pattern (missing ']'))");
+  fails("percent", r#"string.match("a", "%")"#, "bad argument #2 (malformed pattern (ends with '%'))");
+  fails("capture", r#"string.match("a", "(a")"#, "bad argument #2 (unfinished capture)");
+  fails("backref", r#"string.match("a", "%1")"#, "bad argument #2 (invalid capture index %1)");
+  fails("balance", r#"string.match("a", "%b")"#, "bad argument #2 (malformed pattern (missing arguments to '%b'))");
+  fails("index", r#"string.gsub("a", "a", "%2")"#, "invalid capture index %2 in replacement string");
+  fails("repl", r#"string.gsub("a", "a", true)"#, "bad argument #3 (expected string, table or function got bool)");
+  fails("value", r#"string.gsub("a", "(a)", |x| { return [] })"#, "invalid replacement value (a array)");
+}
+
+#[test]
+fn sort() {
+  run("sort")
+}
+
+#[test]
+fn unsortable() {
+  fails("mixed", r#"array.sort([1, "a"])"#, "attempt to compare a number and string value");
+  fails("bool", "array.sort([true])", "attempt to compare a bool value");
+  fails("keys", r#"array.sort_by([1, 2], |v| { if(v == 1) { return "a" } return 2 })"#, "attempt to compare a string and number value");
+  fails("cmp", "array.sort([1], 2)", "bad argument #2 (expected function got number)");
+  fails("error", r#"array.sort([2, 1], |a, b| { error("boom") })"#, "boom");
+}
+
+#[test]
+fn table() {
+  run("table")
+}
+
+#[test]
+fn table_errors() {
+  fails("keys", "table.keys([1])", "expected table got array");
+  fails("merge", "table.merge({}, 1)", "expected table got number");
+  fails("depth", "let a = [] let b = a for(let i = 0; i < 300; i = i + 1) { let c = [] array.push(b, c) b = c } table.deep_clone(a)", "too many nested tables and arrays");
+}
+
+#[test]
+fn json() {
+  run("json")
+}
+
+#[test]
+fn json_errors() {
+  fails("comma", r#"json.decode("{\"a\": 1,}")"#, "bad argument #1 (expected a string key at line 1 column 9)");
+  fails("end", r#"json.decode("[1, 2")"#, "bad argument #1 (expected ']' got the end of the input at line 1 column 6)");
+  fails("trailing", r#"json.decode("[1]\n  x")"#, "bad argument #1 (unexpected data after the value at line 2 column 3)");
+  fails("literal", r#"json.decode("{\n  \"a\": tru\n}")"#, "bad argument #1 (invalid literal, expected 'true' at line 2 column 11)");
+  fails("escape", r#"json.decode("\"\\q\"")"#, "bad argument #1 (invalid escape sequence at line 1 column 3)");
+  fails("control", r#"json.decode("[\n\"a\tb\"]")"#, "bad argument #1 (control character in string at line 2 column 3)");
+  fails("surrogate", r#"json.decode("\"\\ud800\\n\"")"#, "bad argument #1 (missing low surrogate at line 1 column 9)");
+  fails("number", r#"json.decode("1.")"#, "bad argument #1 (missing digits after '.' at line 1 column 3)");
+  fails("nan", "json.encode(0/0)", "bad argument #1 (cannot encode NaN as JSON)");
+  fails("function", "json.encode(print)", "bad argument #1 (cannot encode a function as JSON)");
+  fails("cycle", "let t = {} t.t = t json.encode(t)", "bad argument #1 (cannot encode a cyclic structure as JSON)");
+  fails("keys", r#"json.encode({ [1]: 1, ["1"]: 2 })"#, r#"bad argument #1 (more than one key encodes as "1")"#);
+  fails("null", "json.null.x = 1", "attempt to modify json.null");
+}
+
+#[test]
+fn io() {
+  run("io")
+}
+
+#[test]
+fn io_errors() {
+  fails("mode", r#"io.open("x", "rw")"#, "bad argument #2 (invalid mode 'rw')");
+  fails("closed", r#"let f = io.open("/dev/null") f.close() f.read("all")"#, "attempt to use a closed file");
+  fails("format", r#"io.open("/dev/null").read("word")"#, r#"bad argument #1 (invalid format "word")"#);
+  fails("count", r#"io.open("/dev/null").read(-1)"#, "bad argument #1 (expected a non-negative integer)");
+  fails("whence", r#"io.open("/dev/null").seek("middle", 0)"#, "bad argument #1 (invalid option 'middle')");
+  fails("write", r#"io.open("/dev/null").write([1])"#, "bad argument #1 (expected string got array)");
+}
+
+#[test]
+fn os() {
+  run("os")
+}
+
+#[test]
+fn os_exit() {
+  exits("none", "os.exit() print(1)", 0, "");
+  exits("code", "print(1) os.exit(3) print(2)", 3, "1\n");
+  exits("true", "os.exit(true)", 0, "");
+  exits("false", "os.exit(false)", 1, "");
+  exits("float", "os.exit(2.0)", 2, "");
+  exits("gc", r#"let t = setmetatable({}, { __gc: |t| { print("finalized") } }) os.exit(4)"#, 4, "finalized\n");
+  exits("gc_error", r#"let t = setmetatable({}, { __gc: |t| { error("boom") } }) os.exit(5)"#, 5, "");
+}
+
+#[test]
+fn os_errors() {
+  fails("range", "os.exit(1 << 40)", "bad argument #1 (exit code out of range)");
+  fails("whole", "os.exit(1.5)", "bad argument #1 (number has no integer representation)");
+  fails("type", r#"os.exit("x")"#, "bad argument #1 (expected number got string)");
+  fails("name", r#"os.setenv("A=B", "x")"#, "bad argument #1 (invalid variable name)");
+  fails("value", r#"os.setenv("A", [1])"#, "bad argument #2 (expected string got array)");
+  fails("remove", r#"os.remove("does/not/exist")"#, "does/not/exist: No such file or directory");
+  fails("rename", r#"os.rename("does/not/exist", "x")"#, "does/not/exist: No such file or directory");
+}